@@ -1,15 +1,286 @@
-use crate::rfid::ScanResult;
+use crate::audit::{Audit, ExpectedTag};
+use crate::find::FindState;
+use crate::labels::Labels;
+use crate::memorydump::DumpState;
+use crate::prompt::Prompt;
+use crate::rfid::{
+    ReaderEvent, ReaderHealth, ScanError, ScanEvent, ScanResult, ScanSettings, SelectMask,
+};
+use crate::settings::SettingsState;
+use crate::tagdb::TagDb;
+use crate::tagtable::epc_name;
+use gs1::epc::tid::TID;
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::sync::mpsc;
 use std::time;
+use tui::layout::Rect;
 
 const INACTIVE_AGE: time::Duration = time::Duration::from_secs(5);
+/// How long to keep RSSI history for
+const HISTORY_AGE: time::Duration = time::Duration::from_secs(120);
+/// Most RSSI samples kept per tag, in case it's read very often
+const HISTORY_LENGTH: usize = 2000;
+/// Read rate is averaged over this period
+const RATE_WINDOW: time::Duration = time::Duration::from_secs(5);
+
+/// The signal strength of one read of a tag
+#[derive(Debug, Clone)]
+pub(crate) struct RssiSample {
+    pub time: time::Instant,
+    pub reader: usize,
+    pub antenna: Option<u8>,
+    pub rssi: i8,
+}
+
+/// Information about a tag accumulated over all of its reads
+#[derive(Debug, Clone)]
+pub(crate) struct TagStats {
+    /// The EPC as a tag URI, or in hex if it can't be decoded, as it's shown in the table
+    pub name: String,
+    /// Lower case EPC, URI, manufacturer and model, to search for text in
+    search_text: String,
+    /// TID which the manufacturer and model in `search_text` are from
+    tid: Option<TID>,
+    pub first_seen: time::Instant,
+    pub best_rssi: Option<i8>,
+    pub antennas: BTreeSet<u8>,
+    /// Recent RSSI readings, oldest first
+    pub history: VecDeque<RssiSample>,
+    /// Number of times the tag has been read in inventory rounds
+    pub reads: u64,
+    /// Number of reads on each antenna
+    pub antenna_reads: BTreeMap<u8, u64>,
+    /// Readers which have seen the tag
+    pub readers: BTreeSet<usize>,
+    /// Number of reads by each reader
+    pub reader_reads: BTreeMap<usize, u64>,
+    /// Times of reads in the last `RATE_WINDOW`, oldest first
+    recent_reads: VecDeque<time::Instant>,
+}
+
+impl TagStats {
+    fn new(result: &ScanResult) -> TagStats {
+        let mut stats = TagStats {
+            name: epc_name(result),
+            search_text: String::new(),
+            tid: None,
+            first_seen: result.last_seen,
+            best_rssi: None,
+            antennas: BTreeSet::new(),
+            history: VecDeque::new(),
+            reads: 0,
+            antenna_reads: BTreeMap::new(),
+            readers: BTreeSet::new(),
+            reader_reads: BTreeMap::new(),
+            recent_reads: VecDeque::new(),
+        };
+        stats.update_search_text(&result.epc);
+        stats
+    }
+
+    /// Make the search text from the EPC and the latest TID, so that searching the table doesn't
+    /// decode every tag again.
+    fn update_search_text(&mut self, epc: &[u8]) {
+        let item = ScanResult {
+            tid: self.tid,
+            ..ScanResult::from_epc(epc.to_owned())
+        };
+        self.search_text = [
+            hex::encode(epc),
+            self.name.to_owned(),
+            item.manufacturer().unwrap_or_default(),
+            item.model().unwrap_or_default(),
+        ]
+        .join("\n")
+        .to_lowercase();
+    }
+
+    fn update(&mut self, result: &ScanResult) {
+        if result.tid.is_some() && result.tid != self.tid {
+            self.tid = result.tid;
+            self.update_search_text(&result.epc);
+        }
+        if result.read {
+            self.reads += 1;
+            if let Some(antenna) = result.antenna {
+                *self.antenna_reads.entry(antenna).or_insert(0) += 1;
+            }
+            *self.reader_reads.entry(result.reader).or_insert(0) += 1;
+            self.recent_reads.push_back(result.last_seen);
+            while self
+                .recent_reads
+                .front()
+                .is_some_and(|time| time.elapsed() > RATE_WINDOW)
+            {
+                self.recent_reads.pop_front();
+            }
+        }
+        if let Some(rssi) = result.rssi {
+            self.history.push_back(RssiSample {
+                time: result.last_seen,
+                reader: result.reader,
+                antenna: result.antenna,
+                rssi,
+            });
+            while self.history.len() > HISTORY_LENGTH
+                || self
+                    .history
+                    .front()
+                    .is_some_and(|sample| sample.time.elapsed() > HISTORY_AGE)
+            {
+                self.history.pop_front();
+            }
+            self.best_rssi = Some(match self.best_rssi {
+                Some(best) => cmp::max(best, rssi),
+                None => rssi,
+            });
+        }
+        if let Some(antenna) = result.antenna {
+            self.antennas.insert(antenna);
+        }
+        self.readers.insert(result.reader);
+    }
+
+    /// Reads over the last `RATE_WINDOW`
+    fn recent_read_count(&self) -> usize {
+        self.recent_reads
+            .iter()
+            .filter(|time| time.elapsed() < RATE_WINDOW)
+            .count()
+    }
+
+    /// Reads per second over the last `RATE_WINDOW`
+    pub fn read_rate(&self) -> f64 {
+        self.recent_read_count() as f64 / RATE_WINDOW.as_secs_f64()
+    }
+}
+
+/// Column the tag table is sorted by
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SortColumn {
+    /// Active tags by EPC, then inactive tags by how long ago they were seen
+    Age,
+    EPC,
+    Label,
+    Manufacturer,
+    Model,
+    RSSI,
+    Antenna,
+    Reader,
+    Reads,
+    Rate,
+    FirstSeen,
+}
+
+const SORT_COLUMNS: &[SortColumn] = &[
+    SortColumn::Age,
+    SortColumn::EPC,
+    SortColumn::Label,
+    SortColumn::Manufacturer,
+    SortColumn::Model,
+    SortColumn::RSSI,
+    SortColumn::Antenna,
+    SortColumn::Reader,
+    SortColumn::Reads,
+    SortColumn::Rate,
+    SortColumn::FirstSeen,
+];
+
+impl SortColumn {
+    /// The next (or previous) column to sort by
+    pub fn next(self, reverse: bool) -> SortColumn {
+        let count = SORT_COLUMNS.len();
+        let i = SORT_COLUMNS.iter().position(|c| *c == self).unwrap();
+        SORT_COLUMNS[if reverse { (i + count - 1) % count } else { (i + 1) % count }]
+    }
+
+    /// Whether larger values come first unless the order is reversed, so the strongest or most
+    /// often read tags are at the top
+    pub fn descending(self) -> bool {
+        matches!(self, SortColumn::RSSI | SortColumn::Reads | SortColumn::Rate)
+    }
+}
+
+/// The reply to a command sent to a reader without waiting for it
+trait Reply {
+    /// The command's error, once the reader has replied. The reader stopping isn't an error
+    /// here, as the status bar already shows it.
+    fn poll(&self) -> Option<Result<(), String>>;
+}
+
+impl<T> Reply for mpsc::Receiver<Result<T, failure::Error>> {
+    fn poll(&self) -> Option<Result<(), String>> {
+        match self.try_recv() {
+            Ok(result) => Some(result.map(|_| ()).map_err(|err| err.to_string())),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Ok(())),
+        }
+    }
+}
+
+/// A command which a reader hasn't replied to yet
+struct PendingReply {
+    reader: usize,
+    command: &'static str,
+    reply: Box<dyn Reply>,
+}
+
+/// What a reader's scan thread has reported, for the status bar
+#[derive(Default)]
+pub(crate) struct ReaderStatus {
+    /// Reads in the last inventory round
+    pub round_reads: usize,
+    pub last_round: Option<time::Instant>,
+    /// The most recent error, and when it happened
+    pub error: Option<(ScanError, time::Instant)>,
+    /// Number of errors this session
+    pub errors: u64,
+    /// The scan thread has stopped, so no more tags will be seen
+    pub stopped: bool,
+    /// The reader's latest health, or None until it's been checked
+    pub health: Option<ReaderHealth>,
+    /// Attempts to reconnect since the connection to the reader was lost, if it has been
+    pub reconnecting: Option<u32>,
+}
 
 pub(crate) struct App {
     pub items: HashMap<Vec<u8>, ScanResult>,
+    pub stats: HashMap<Vec<u8>, TagStats>,
     pub selected: Option<Vec<u8>>,
     pub show_inactive: bool,
+    pub prompt: Option<Prompt>,
+    pub dump: Option<DumpState>,
+    /// Select mask which inventory is restricted to
+    pub filter: Option<SelectMask>,
+    pub settings: SettingsState,
+    pub show_settings: bool,
+    pub find: Option<FindState>,
+    pub sort: SortColumn,
+    /// Reverse the usual order of `sort`
+    pub sort_reverse: bool,
+    /// Only show tags whose EPC, URI, label, manufacturer or model contains this text
+    pub search: Option<String>,
+    /// Index of the first tag shown in the table
+    pub scroll: usize,
+    /// Area of the screen taken up by table rows when it was last drawn
+    pub table_rows: Rect,
+    /// Database of tags seen in this and earlier sessions
+    pub db: Option<TagDb>,
+    pub labels: Labels,
+    /// Tags expected to be seen, for the audit view
+    pub expected: Vec<ExpectedTag>,
+    pub show_audit: bool,
+    pub audit_scroll: usize,
+    /// The readers in the session, as PORT:DRIVER
+    pub readers: Vec<String>,
+    /// Only show tags which have been seen by this reader
+    pub reader_filter: Option<usize>,
+    /// The status of each reader
+    pub status: Vec<ReaderStatus>,
+    /// Commands sent without waiting for their replies, whose errors are shown in the status bar
+    pending: Vec<PendingReply>,
 }
 
 impl ScanResult {
@@ -19,40 +290,131 @@ impl ScanResult {
 }
 
 impl App {
-    pub fn new() -> App {
+    pub fn new(readers: Vec<String>) -> App {
         App {
             items: HashMap::new(),
+            stats: HashMap::new(),
             selected: None,
             show_inactive: false,
+            prompt: None,
+            dump: None,
+            filter: None,
+            settings: SettingsState::new(ScanSettings::default(), Vec::new(), false, None),
+            show_settings: false,
+            find: None,
+            sort: SortColumn::Age,
+            sort_reverse: false,
+            search: None,
+            scroll: 0,
+            table_rows: Rect::default(),
+            db: None,
+            labels: Labels::empty(),
+            expected: Vec::new(),
+            show_audit: false,
+            audit_scroll: 0,
+            status: readers.iter().map(|_| ReaderStatus::default()).collect(),
+            readers,
+            reader_filter: None,
+            pending: Vec::new(),
         }
     }
 
-    pub fn update_items(&mut self, rx: &mpsc::Receiver<ScanResult>) {
+    /// Keep the reply to a command sent to a reader without waiting for it, to show in the
+    /// status bar if the command fails.
+    pub fn expect_reply<T: 'static>(
+        &mut self,
+        reader: usize,
+        command: &'static str,
+        reply: mpsc::Receiver<Result<T, failure::Error>>,
+    ) {
+        self.pending.push(PendingReply {
+            reader,
+            command,
+            reply: Box::new(reply),
+        });
+    }
+
+    /// Show the errors from commands which readers have replied to.
+    fn check_replies(&mut self) {
+        let status = &mut self.status;
+        self.pending.retain(|pending| match pending.reply.poll() {
+            Some(Err(err)) => {
+                let status = &mut status[pending.reader];
+                status.error = Some((
+                    ScanError::Command(pending.command, err),
+                    time::Instant::now(),
+                ));
+                status.errors += 1;
+                false
+            }
+            Some(Ok(())) => false,
+            None => true,
+        });
+    }
+
+    pub fn update_items(&mut self, rx: &mpsc::Receiver<ReaderEvent>) {
+        self.check_replies();
         loop {
-            match rx.try_recv() {
-                Ok(result) => {
-                    let epc = result.epc.to_vec();
-                    match self.items.get_mut(&epc) {
-                        Some(item) => {
-                            item.update(result);
-                        }
-                        None => {
-                            self.items.insert(epc, result);
-                        }
-                    };
-                }
-                Err(_) => {
+            let (status, event) = match rx.try_recv() {
+                Ok(ReaderEvent { reader, event }) => (&mut self.status[reader], event),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    for status in self.status.iter_mut() {
+                        status.stopped = true;
+                    }
                     break;
                 }
             };
-        }
-        let items = self.get_items();
-        if items.len() > 0 {
-            match self.selected {
+            let result = match event {
+                ScanEvent::Tag(result) => result,
+                ScanEvent::Round(reads) => {
+                    status.round_reads = reads;
+                    status.last_round = Some(time::Instant::now());
+                    continue;
+                }
+                ScanEvent::Error(err) => {
+                    status.error = Some((err, time::Instant::now()));
+                    status.errors += 1;
+                    continue;
+                }
+                ScanEvent::Connected(health) => {
+                    status.health = Some(health);
+                    status.reconnecting = None;
+                    continue;
+                }
+                ScanEvent::Reconnecting(attempt) => {
+                    status.reconnecting = Some(attempt);
+                    continue;
+                }
+                ScanEvent::Stopped => {
+                    status.stopped = true;
+                    continue;
+                }
+            };
+            let epc = result.epc.to_vec();
+            if let Some(find) = &mut self.find {
+                find.update(&result);
+            }
+            if let Some(db) = &mut self.db {
+                db.update(&result);
+            }
+            self.stats
+                .entry(epc.to_vec())
+                .or_insert_with(|| TagStats::new(&result))
+                .update(&result);
+            match self.items.get_mut(&epc) {
+                Some(item) => {
+                    item.update(result);
+                }
                 None => {
-                    self.selected = Some(items[0].epc.to_vec());
+                    self.items.insert(epc, result);
                 }
-                _ => {}
+            };
+        }
+        let items = self.get_items();
+        if !items.is_empty() {
+            if self.selected.is_none() {
+                self.selected = Some(items[0].epc.to_vec());
             }
         } else {
             self.selected = None;
@@ -61,6 +423,9 @@ impl App {
 
     pub fn update_selected(&mut self, reverse: bool) {
         let items = self.get_items();
+        if items.is_empty() {
+            return;
+        }
 
         let selected = match &self.selected {
             Some(epc) => epc.to_vec(),
@@ -91,21 +456,174 @@ impl App {
         self.selected = Some(items[selected_index].epc.to_vec());
     }
 
-    pub fn get_items(&self) -> Vec<&ScanResult> {
-        let mut items: Vec<&ScanResult> = self.items.iter().filter_map(
-            |(_, item)| 
-            if !item.active() && !self.show_inactive {
-                None
-            } else {
-                Some(item)
+    /// Select the tag at `index` in the table, or the closest one to it.
+    fn select_index(&mut self, index: isize) {
+        let items = self.get_items();
+        if items.is_empty() {
+            return;
+        }
+        let index = index.clamp(0, items.len() as isize - 1) as usize;
+        self.selected = Some(items[index].epc.to_vec());
+    }
+
+    /// Index of the selected tag in `items`.
+    fn selected_index(&self, items: &[&ScanResult]) -> Option<usize> {
+        let selected = self.selected.as_ref()?;
+        items.iter().position(|item| &item.epc == selected)
+    }
+
+    /// Move the selection by `delta` rows, without wrapping around.
+    pub fn move_selected(&mut self, delta: isize) {
+        let index = self.selected_index(&self.get_items()).unwrap_or(0) as isize;
+        self.select_index(index + delta);
+    }
+
+    /// Number of rows in a page of the table
+    pub fn page_size(&self) -> isize {
+        cmp::max(self.table_rows.height, 1) as isize
+    }
+
+    /// Scroll the audit view by `delta` rows, keeping at least one row in view.
+    pub fn scroll_audit(&mut self, delta: isize) {
+        let last = Audit::new(self).rows.len().saturating_sub(1);
+        let scroll = self.audit_scroll as isize + delta;
+        self.audit_scroll = cmp::min(cmp::max(scroll, 0) as usize, last);
+    }
+
+    /// Sort by the next (or previous) column, skipping the reader if there's only one.
+    pub fn next_sort(&mut self, reverse: bool) {
+        self.sort = self.sort.next(reverse);
+        if self.sort == SortColumn::Reader && self.readers.len() < 2 {
+            self.sort = self.sort.next(reverse);
+        }
+    }
+
+    /// Show only the tags seen by the next reader, or by any reader after the last. Does
+    /// nothing with a single reader.
+    pub fn next_reader_filter(&mut self) {
+        self.reader_filter = match self.reader_filter {
+            None if self.readers.len() > 1 => Some(0),
+            Some(reader) if reader + 1 < self.readers.len() => Some(reader + 1),
+            _ => None,
+        };
+    }
+
+    pub fn select_first(&mut self) {
+        self.select_index(0);
+    }
+
+    pub fn select_last(&mut self) {
+        self.select_index(isize::MAX);
+    }
+
+    /// Select the tag at a row on the screen, if there's one there.
+    pub fn click(&mut self, x: u16, y: u16) {
+        let rows = self.table_rows;
+        if x >= rows.left() && x < rows.right() && y >= rows.top() && y < rows.bottom() {
+            let index = self.scroll + (y - rows.top()) as usize;
+            if index < self.get_items().len() {
+                self.select_index(index as isize);
             }
-        ).collect();
-        items.sort_by_key(|res| {
-            (
-                cmp::max(res.last_seen.elapsed(), INACTIVE_AGE),
-                res.epc.to_owned(),
-            )
-        });
+        }
+    }
+
+    /// Scroll position of the table which keeps the selected tag visible, given the table's
+    /// `items` and the area its rows are drawn in.
+    pub fn scroll_to_selected(&self, items: &[&ScanResult], rows: Rect) -> usize {
+        let height = rows.height as usize;
+        let mut scroll = self.scroll;
+        if let Some(index) = self.selected_index(items) {
+            if index < scroll {
+                scroll = index;
+            } else if index >= scroll + height {
+                scroll = index + 1 - height;
+            }
+        }
+        // Don't leave empty rows at the bottom when there are tags above
+        cmp::min(scroll, items.len().saturating_sub(height))
+    }
+
+    pub fn get_items(&self) -> Vec<&ScanResult> {
+        let search = self.search.as_ref().map(|search| search.to_lowercase());
+        let mut items: Vec<&ScanResult> = self
+            .items
+            .values()
+            .filter(|item| item.active() || self.show_inactive)
+            .filter(|item| match &self.filter {
+                // Hide tags seen before the filter was set
+                Some(filter) => filter.matches_epc(&item.epc) != Some(false),
+                None => true,
+            })
+            .filter(|item| match &search {
+                Some(search) => self.matches_search(item, search),
+                None => true,
+            })
+            .filter(|item| match self.reader_filter {
+                Some(reader) => self
+                    .stats
+                    .get(&item.epc)
+                    .is_some_and(|stats| stats.readers.contains(&reader)),
+                None => true,
+            })
+            .collect();
+        // Ties stay in EPC order, as the sort by column is stable
+        items.sort_by(|a, b| a.epc.cmp(&b.epc));
+        self.sort_items(&mut items);
         items
     }
+
+    /// Whether a tag's details or label contain `search`, which should be lower case.
+    fn matches_search(&self, item: &ScanResult, search: &str) -> bool {
+        self.stats
+            .get(&item.epc)
+            .is_some_and(|stats| stats.search_text.contains(search))
+            || self
+                .labels
+                .get(&item.epc)
+                .is_some_and(|annotation| annotation.matches(search))
+    }
+
+    /// Sort `items` by the sort column, computing each tag's key only once.
+    fn sort_items(&self, items: &mut [&ScanResult]) {
+        let reverse = self.sort.descending() != self.sort_reverse;
+        let stats = |item: &ScanResult| self.stats.get(&item.epc);
+        match self.sort {
+            SortColumn::Age => sort_by_key(items, reverse, |item| {
+                cmp::max(item.last_seen.elapsed(), INACTIVE_AGE)
+            }),
+            SortColumn::EPC => sort_by_key(items, reverse, |item| match stats(item) {
+                Some(stats) => stats.name.to_owned(),
+                None => epc_name(item),
+            }),
+            // Unlabelled tags last
+            SortColumn::Label => {
+                sort_by_key(items, reverse, |item| match self.labels.get(&item.epc) {
+                    Some(annotation) => (false, annotation.to_string()),
+                    None => (true, String::new()),
+                })
+            }
+            SortColumn::Manufacturer => sort_by_key(items, reverse, ScanResult::manufacturer),
+            SortColumn::Model => sort_by_key(items, reverse, ScanResult::model),
+            SortColumn::RSSI => sort_by_key(items, reverse, |item| item.rssi),
+            SortColumn::Antenna => sort_by_key(items, reverse, |item| item.antenna),
+            SortColumn::Reader => sort_by_key(items, reverse, |item| item.reader),
+            SortColumn::Reads => sort_by_key(items, reverse, |item| stats(item).map(|s| s.reads)),
+            // The rate is proportional to the number of recent reads
+            SortColumn::Rate => sort_by_key(items, reverse, |item| {
+                stats(item).map_or(0, TagStats::recent_read_count)
+            }),
+            SortColumn::FirstSeen => sort_by_key(items, reverse, |item| {
+                cmp::Reverse(stats(item).map(|s| s.first_seen))
+            }),
+        }
+    }
+}
+
+/// Sort `items` by `key`, which is computed once for each item.
+fn sort_by_key<K: Ord>(items: &mut [&ScanResult], reverse: bool, key: impl Fn(&ScanResult) -> K) {
+    if reverse {
+        items.sort_by_cached_key(|item| cmp::Reverse(key(item)));
+    } else {
+        items.sort_by_cached_key(|item| key(item));
+    }
 }
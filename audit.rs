@@ -0,0 +1,345 @@
+//! Reconciliation of the tags seen against a list of expected tags, for inventory audits.
+use crate::app::App;
+use crate::block;
+use crate::encode::parse_epc;
+use crate::export::wall_clock;
+use crate::rfid::ScanResult;
+use crate::tagtable::short_duration;
+use failure::{bail, format_err};
+use gs1::epc;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::time;
+use tui::buffer::Buffer;
+use tui::layout::{Constraint, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Paragraph, Text, Widget};
+
+/// How an expected tag is identified
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq)]
+enum ExpectedId {
+    /// A binary EPC, given in hex or as an EPC tag URI
+    EPC(Vec<u8>),
+    /// An EPC pure identity URI such as `urn:epc:id:sgtin:0614141.812345.6789`, which matches
+    /// whatever the tag's filter value is
+    URI(String),
+}
+
+pub(crate) struct ExpectedTag {
+    id: ExpectedId,
+    /// The ID as it was given in the list
+    pub name: String,
+    pub description: String,
+}
+
+fn parse_id(input: &str) -> Result<ExpectedId, failure::Error> {
+    if input.starts_with("urn:epc:id:") {
+        Ok(ExpectedId::URI(input.to_string()))
+    } else {
+        Ok(ExpectedId::EPC(parse_epc(input)?))
+    }
+}
+
+/// Load a CSV of expected tags, with an EPC (in hex or as a URI) in the first column and an
+/// optional description in the second. A header row is skipped.
+pub(crate) fn load(path: &str) -> Result<Vec<ExpectedTag>, failure::Error> {
+    let file = File::open(path).map_err(|e| format_err!("Unable to open {}: {}", path, e))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(file);
+    let mut tags: Vec<ExpectedTag> = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format_err!("{}: {}", path, e))?;
+        let name = match record.get(0) {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+        let id = match parse_id(name) {
+            Ok(id) => id,
+            Err(_) if i == 0 => continue,
+            Err(err) => bail!("{}:{}: {}", path, i + 1, err),
+        };
+        if tags.iter().any(|tag| tag.id == id) {
+            bail!("{}:{}: {} is listed more than once", path, i + 1, name);
+        }
+        tags.push(ExpectedTag {
+            id,
+            name: name.to_string(),
+            description: record.get(1).unwrap_or_default().to_string(),
+        });
+    }
+    Ok(tags)
+}
+
+/// Rows are shown in this order, so the tags which need looking at come first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum AuditStatus {
+    Missing,
+    Unexpected,
+    Seen,
+}
+
+impl fmt::Display for AuditStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                AuditStatus::Missing => "missing",
+                AuditStatus::Unexpected => "unexpected",
+                AuditStatus::Seen => "seen",
+            }
+        )
+    }
+}
+
+pub(crate) struct AuditRow {
+    pub status: AuditStatus,
+    /// The ID from the expected list, or the EPC of an unexpected tag
+    pub id: String,
+    pub description: String,
+    pub reads: u64,
+    pub last_seen: Option<time::Instant>,
+}
+
+/// The expected tags, and every tag seen this session, by whether they were expected and seen
+pub(crate) struct Audit {
+    pub rows: Vec<AuditRow>,
+}
+
+impl Audit {
+    pub fn new(app: &App) -> Audit {
+        let expected = &app.expected;
+        let mut by_epc = HashMap::new();
+        let mut by_uri = HashMap::new();
+        for (i, tag) in expected.iter().enumerate() {
+            match &tag.id {
+                ExpectedId::EPC(epc) => by_epc.insert(epc.as_slice(), i),
+                ExpectedId::URI(uri) => by_uri.insert(uri.as_str(), i),
+            };
+        }
+
+        let mut found: Vec<Vec<&ScanResult>> = vec![Vec::new(); expected.len()];
+        let mut unexpected = Vec::new();
+        for item in app.items.values() {
+            let index = by_epc.get(item.epc.as_slice()).cloned().or_else(|| {
+                epc::decode_binary(&item.epc)
+                    .ok()
+                    .and_then(|val| by_uri.get(val.to_uri().as_str()).cloned())
+            });
+            match index {
+                Some(i) => found[i].push(item),
+                None => unexpected.push(item),
+            }
+        }
+
+        let reads = |item: &ScanResult| app.stats.get(&item.epc).map_or(0, |stats| stats.reads);
+        let mut rows: Vec<AuditRow> = expected
+            .iter()
+            .zip(found)
+            .map(|(tag, items)| AuditRow {
+                status: if items.is_empty() {
+                    AuditStatus::Missing
+                } else {
+                    AuditStatus::Seen
+                },
+                id: tag.name.to_owned(),
+                description: tag.description.to_owned(),
+                reads: items.iter().map(|item| reads(item)).sum(),
+                last_seen: items.iter().map(|item| item.last_seen).max(),
+            })
+            .collect();
+        rows.extend(unexpected.into_iter().map(|item| {
+            AuditRow {
+                status: AuditStatus::Unexpected,
+                id: hex::encode_upper(&item.epc),
+                description: app
+                    .labels
+                    .get(&item.epc)
+                    .map(|label| label.to_string())
+                    .unwrap_or_default(),
+                reads: reads(item),
+                last_seen: Some(item.last_seen),
+            }
+        }));
+        rows.sort_by(|a, b| a.status.cmp(&b.status).then_with(|| a.id.cmp(&b.id)));
+        Audit { rows }
+    }
+
+    pub fn count(&self, status: AuditStatus) -> usize {
+        self.rows.iter().filter(|row| row.status == status).count()
+    }
+
+    pub fn summary(&self) -> String {
+        let seen = self.count(AuditStatus::Seen);
+        format!(
+            "{} of {} expected seen, {} missing, {} unexpected",
+            seen,
+            seen + self.count(AuditStatus::Missing),
+            self.count(AuditStatus::Missing),
+            self.count(AuditStatus::Unexpected)
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct ExportRow {
+    status: String,
+    id: String,
+    description: String,
+    reads: u64,
+    last_seen: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ExportReport {
+    expected: usize,
+    seen: usize,
+    missing: usize,
+    unexpected: usize,
+    tags: Vec<ExportRow>,
+}
+
+/// Write the audit as JSON, with counts, or as CSV with a row per tag.
+pub(crate) fn write(
+    audit: &Audit,
+    format: &str,
+    out: &mut dyn Write,
+) -> Result<(), failure::Error> {
+    let rows = audit.rows.iter().map(|row| ExportRow {
+        status: row.status.to_string(),
+        id: row.id.to_owned(),
+        description: row.description.to_owned(),
+        reads: row.reads,
+        last_seen: row.last_seen.map(|time| wall_clock(time).to_rfc3339()),
+    });
+    match format {
+        "json" => {
+            let report = ExportReport {
+                expected: audit.count(AuditStatus::Seen) + audit.count(AuditStatus::Missing),
+                seen: audit.count(AuditStatus::Seen),
+                missing: audit.count(AuditStatus::Missing),
+                unexpected: audit.count(AuditStatus::Unexpected),
+                tags: rows.collect(),
+            };
+            serde_json::to_writer_pretty(&mut *out, &report)?;
+            writeln!(out)?;
+        }
+        "csv" => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(["status", "id", "description", "reads", "last_seen"])?;
+            for row in rows {
+                writer.write_record([
+                    row.status,
+                    row.id,
+                    row.description,
+                    row.reads.to_string(),
+                    row.last_seen.unwrap_or_default(),
+                ])?;
+            }
+            writer.flush()?;
+        }
+        _ => bail!("Invalid output format (shouldn't happen)"),
+    }
+    Ok(())
+}
+
+/// Write the audit to `path`, as JSON if it ends in `.json` and CSV otherwise.
+pub(crate) fn write_file(audit: &Audit, path: &str) -> Result<(), failure::Error> {
+    let format = if path.ends_with(".json") {
+        "json"
+    } else {
+        "csv"
+    };
+    let mut file =
+        File::create(path).map_err(|e| format_err!("Unable to create {}: {}", path, e))?;
+    write(audit, format, &mut file)
+}
+
+pub(crate) struct AuditView<'a> {
+    pub audit: &'a Audit,
+    pub scroll: usize,
+}
+
+impl<'a> AuditView<'a> {
+    pub fn new(audit: &'a Audit, scroll: usize) -> AuditView<'a> {
+        AuditView { audit, scroll }
+    }
+}
+
+fn status_style(status: AuditStatus) -> Style {
+    Style::default().fg(match status {
+        AuditStatus::Missing => Color::Red,
+        AuditStatus::Unexpected => Color::Yellow,
+        AuditStatus::Seen => Color::Green,
+    })
+}
+
+impl<'a> Widget for AuditView<'a> {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        let title = format!("Audit: {}", self.audit.summary());
+        let mut outer = block(&title);
+        outer.draw(area, buf);
+        let rects = Layout::default()
+            .constraints(
+                [
+                    Constraint::Length(1),
+                    Constraint::Min(0),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(outer.inner(area));
+
+        let header = format!(
+            "{:<11}{:<50}{:<40}{:>7}  {}\n",
+            "Status", "ID", "Description", "Reads", "Last seen"
+        );
+        Paragraph::new(
+            [Text::styled(
+                header,
+                Style::default().modifier(Modifier::BOLD),
+            )]
+            .iter(),
+        )
+        .draw(rects[0], buf);
+
+        let text: Vec<Text> = self
+            .audit
+            .rows
+            .iter()
+            .skip(self.scroll)
+            .take(rects[1].height as usize)
+            .map(|row| {
+                Text::styled(
+                    format!(
+                        "{:<11}{:<50}{:<40.39}{:>7}  {}\n",
+                        row.status.to_string(),
+                        row.id,
+                        row.description,
+                        row.reads,
+                        match row.last_seen {
+                            Some(time) => format!("{} ago", short_duration(time.elapsed())),
+                            None => "-".to_string(),
+                        }
+                    ),
+                    status_style(row.status),
+                )
+            })
+            .collect();
+        Paragraph::new(text.iter()).draw(rects[1], buf);
+
+        let help = if self.audit.rows.is_empty() {
+            "No tags expected or seen. Load a list of expected tags with --expected FILE."
+        } else {
+            "Up/Down/PageUp/PageDown to scroll, x to export, Esc to close."
+        };
+        Paragraph::new([Text::raw(help)].iter()).draw(rects[2], buf);
+    }
+}
@@ -0,0 +1,75 @@
+//! Reader settings kept in a file, so a reader's options don't have to be given every time.
+use crate::drivers;
+use failure::{bail, format_err};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+
+/// A JSON file such as:
+///
+/// `{"port": "/dev/ttyUSB0", "driver": "invelion", "options": {"baud": 38400, "address": [1, 2]}}`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    port: Option<String>,
+    driver: Option<String>,
+    /// Driver options, added after the port as `key=value`. A list gives the option once for
+    /// each value.
+    #[serde(default)]
+    options: BTreeMap<String, Value>,
+}
+
+fn option_values(key: &str, value: &Value) -> Result<Vec<String>, failure::Error> {
+    Ok(match value {
+        Value::String(value) => vec![value.to_owned()],
+        Value::Number(value) => vec![value.to_string()],
+        Value::Bool(value) => vec![value.to_string()],
+        Value::Array(values) => {
+            let mut result = Vec::new();
+            for value in values {
+                match value {
+                    Value::Array(_) => bail!("Option '{}' can't contain a list", key),
+                    value => result.extend(option_values(key, value)?),
+                }
+            }
+            result
+        }
+        _ => bail!("Option '{}' must be a string, number or list", key),
+    })
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, failure::Error> {
+        let file = File::open(path).map_err(|e| format_err!("Unable to open {}: {}", path, e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format_err!("{}: invalid config: {}", path, e))
+    }
+
+    /// The PORT and DRIVER to use, from the command line if they're given there and otherwise
+    /// from the file, with the options from the file added to the PORT.
+    pub fn reader(
+        &self,
+        port: Option<&str>,
+        driver: Option<&str>,
+    ) -> Result<(String, String), failure::Error> {
+        let driver = match driver.or(self.driver.as_deref()) {
+            Some(driver) if drivers::DRIVERS.contains(&driver) => driver,
+            Some(driver) => bail!("Unknown driver '{}' in the config file", driver),
+            None => bail!("No DRIVER given on the command line or in the config file"),
+        };
+        let port = match port.or(self.port.as_deref()) {
+            Some(port) => port,
+            None => bail!("No PORT given on the command line or in the config file"),
+        };
+        let mut spec = vec![port.to_string()];
+        for (key, value) in &self.options {
+            for value in option_values(key, value)? {
+                spec.push(format!("{}={}", key, value));
+            }
+        }
+        spec.retain(|part| !part.is_empty());
+        Ok((spec.join(","), driver.to_string()))
+    }
+}
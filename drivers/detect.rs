@@ -0,0 +1,283 @@
+//! Finding readers by trying each serial driver's identification command on each serial port.
+//!
+//! This talks to the ports directly rather than through the driver crates, so it can try other
+//! baud rates and give up quickly on ports with nothing attached.
+use super::invelion;
+use super::port::configure;
+use super::ru5102;
+use super::usb;
+use failure::bail;
+use log::debug;
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::time::Duration;
+
+/// Device names in /dev which are likely to be serial ports a reader is plugged into
+const PORT_PREFIXES: &[&str] = &[
+    "ttyUSB",
+    "ttyACM",
+    "cu.usbserial",
+    "cu.SLAB",
+    "cu.wchusbserial",
+];
+/// How long to wait for a reader to start or carry on responding
+const TIMEOUT: Duration = Duration::from_millis(200);
+/// Address which every reader responds to
+const BROADCAST: u8 = 0xFF;
+
+struct Probe {
+    driver: &'static str,
+    /// Baud rates the reader can be set to, with the one the driver uses by default first
+    bauds: &'static [usize],
+    /// The address the driver sends commands to by default
+    address: u8,
+    /// Whether the driver can be given another address
+    any_address: bool,
+    command: fn(u8) -> Vec<u8>,
+    /// Parse a response into the reader's address and firmware version
+    response: fn(&[u8]) -> Option<(u8, String)>,
+}
+
+const PROBES: &[Probe] = &[
+    Probe {
+        driver: "ru5102",
+        bauds: &[57600, 9600, 19200, 38400, 115200],
+        address: 0,
+        any_address: false,
+        command: ru5102_command,
+        response: ru5102_response,
+    },
+    Probe {
+        driver: "invelion",
+        bauds: &[115200, 38400],
+        address: 1,
+        any_address: true,
+        command: invelion_command,
+        response: invelion_response,
+    },
+];
+
+/// Get Reader Information
+fn ru5102_command(address: u8) -> Vec<u8> {
+    ru5102::frame(address, 0x21, &[])
+}
+
+fn ru5102_response(data: &[u8]) -> Option<(u8, String)> {
+    (0..data.len()).find_map(|start| {
+        let pkt = data.get(start..start + 1 + *data.get(start)? as usize)?;
+        let (body, crc) = pkt.split_at(pkt.len().checked_sub(2)?);
+        if body.len() < 6 || body[2] != 0x21 || ru5102::crc(body).to_le_bytes() != crc {
+            return None;
+        }
+        Some((body[1], format!("{}.{}", body[4], body[5])))
+    })
+}
+
+/// Get Firmware Version
+fn invelion_command(address: u8) -> Vec<u8> {
+    invelion::frame(address, 0x72, &[])
+}
+
+fn invelion_response(data: &[u8]) -> Option<(u8, String)> {
+    (0..data.len()).find_map(|start| {
+        if data[start] != 0xA0 {
+            return None;
+        }
+        let pkt = data.get(start..start + 2 + *data.get(start + 1)? as usize)?;
+        let (body, check) = pkt.split_at(pkt.len() - 1);
+        if body.len() < 6 || body[3] != 0x72 || invelion::checksum(body) != check[0] {
+            return None;
+        }
+        Some((body[2], format!("{}.{}", body[4], body[5])))
+    })
+}
+
+/// A reader which responded
+pub(crate) struct Detected {
+    pub port: String,
+    pub driver: &'static str,
+    pub baud: usize,
+    pub address: u8,
+    pub firmware: String,
+    /// Options to give the driver after the port, for a reader not at its defaults
+    options: String,
+    /// Whether the driver can talk to it
+    pub usable: bool,
+}
+
+impl Detected {
+    /// The PORT to give the driver for this reader.
+    pub fn spec(&self) -> String {
+        format!("{}{}", self.port, self.options)
+    }
+}
+
+impl fmt::Display for Detected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: firmware {}, {} baud, address {}",
+            self.port, self.driver, self.firmware, self.baud, self.address
+        )?;
+        if let Some(device) = usb::identify(&self.port) {
+            write!(f, ", {}", device)?;
+        }
+        if !self.usable {
+            write!(f, " (not usable: the driver only supports address 0)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Serial ports which might have a reader attached.
+fn serial_ports() -> Vec<String> {
+    let mut ports: Vec<String> = fs::read_dir("/dev")
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| PORT_PREFIXES.iter().any(|prefix| name.starts_with(prefix)))
+                .map(|name| format!("/dev/{}", name))
+                .collect()
+        })
+        .unwrap_or_default();
+    ports.sort();
+    ports
+}
+
+/// Send a command at the given baud rate, and return whatever comes back before it goes quiet.
+fn exchange(port: &str, baud: usize, command: &[u8]) -> Result<Vec<u8>, failure::Error> {
+    let mut serial = serial::open(port)?;
+    configure(&mut serial, baud, TIMEOUT)?;
+    serial.write_all(command)?;
+    let mut response = Vec::new();
+    let mut buf = [0u8; 64];
+    // Stop at a timeout, or if the port keeps sending, which a reader wouldn't
+    while response.len() < 256 {
+        match serial.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => response.extend(&buf[..n]),
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(response)
+}
+
+/// Try each driver on `port`, returning the first which responds.
+fn probe_port(port: &str, driver: Option<&str>) -> Option<Detected> {
+    for probe in PROBES {
+        if driver.is_some_and(|driver| driver != probe.driver) {
+            continue;
+        }
+        for &baud in probe.bauds {
+            let response = match exchange(port, baud, &(probe.command)(BROADCAST)) {
+                Ok(response) => response,
+                Err(err) => {
+                    debug!("Unable to probe {}: {}", port, err);
+                    return None;
+                }
+            };
+            debug!(
+                "{} at {} baud on {}: {:?}",
+                probe.driver, baud, port, response
+            );
+            if let Some((address, firmware)) = (probe.response)(&response) {
+                let mut options = String::new();
+                if baud != probe.bauds[0] {
+                    options.push_str(&format!(",baud={}", baud));
+                }
+                if address != probe.address && probe.any_address {
+                    options.push_str(&format!(",address={}", address));
+                }
+                return Some(Detected {
+                    port: port.to_string(),
+                    driver: probe.driver,
+                    baud,
+                    address,
+                    firmware,
+                    options,
+                    usable: address == probe.address || probe.any_address,
+                });
+            }
+        }
+    }
+    None
+}
+
+/// Look for readers on `port`, or every likely serial port, with `driver` or every serial driver.
+pub(crate) fn detect(
+    port: Option<&str>,
+    driver: Option<&str>,
+) -> Result<Vec<Detected>, failure::Error> {
+    if let Some(driver) = driver {
+        if !PROBES.iter().any(|probe| probe.driver == driver) {
+            bail!("The {} driver can't be detected", driver);
+        }
+    }
+    let ports = match port {
+        Some(port) => vec![port.to_string()],
+        None => serial_ports(),
+    };
+    if ports.is_empty() {
+        bail!("No serial ports found");
+    }
+    Ok(ports
+        .iter()
+        .filter_map(|port| probe_port(port, driver))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(value: &str) -> Vec<u8> {
+        hex::decode(value).unwrap()
+    }
+
+    #[test]
+    fn frames_commands() {
+        assert_eq!(ru5102_command(0), bytes("040021D96A"));
+        assert_eq!(ru5102_command(BROADCAST), bytes("04FF211995"));
+        assert_eq!(invelion_command(1), bytes("A0030172EA"));
+    }
+
+    #[test]
+    fn parses_ru5102_responses() {
+        let response = bytes("0D002100021E08034E001E0A4189");
+        assert_eq!(ru5102_response(&response), Some((0, "2.30".to_string())));
+        // After a partial frame or noise
+        let mut noisy = bytes("0D0021");
+        noisy.extend(&response);
+        assert_eq!(ru5102_response(&noisy), Some((0, "2.30".to_string())));
+        // Bad CRC
+        assert_eq!(
+            ru5102_response(&bytes("0D002100021E08034E001E0A4188")),
+            None
+        );
+        // Truncated
+        assert_eq!(ru5102_response(&response[..response.len() - 1]), None);
+        // Reply to another command
+        assert_eq!(ru5102_response(&ru5102::frame(0, 0x01, &[0; 8])), None);
+    }
+
+    #[test]
+    fn parses_invelion_responses() {
+        let response = bytes("A00501720803DD");
+        assert_eq!(invelion_response(&response), Some((1, "8.3".to_string())));
+        let mut noisy = bytes("00A0");
+        noisy.extend(&response);
+        assert_eq!(invelion_response(&noisy), Some((1, "8.3".to_string())));
+        // Bad checksum
+        assert_eq!(invelion_response(&bytes("A00501720803DE")), None);
+        assert_eq!(invelion_response(&response[..response.len() - 1]), None);
+        assert_eq!(
+            invelion_response(&invelion::frame(1, 0x74, &[0x10, 0])),
+            None
+        );
+        // The command itself is too short to be a response
+        assert_eq!(invelion_response(&invelion_command(1)), None);
+    }
+}
@@ -0,0 +1,627 @@
+use super::port::{parse_baud, PortHandle};
+use crate::rfid::{
+    parse_tid, read_xpc_w1, to_word, AccessError, Capabilities, LockAction, LockTarget, MemoryBank,
+    Passwords, Reader, ReaderHealth, ScanResult, ScanSettings, Session, Target,
+};
+use failure::{bail, format_err};
+use invelion::error::Error;
+use invelion::protocol::{self, ResponseCode};
+use log::debug;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+const DEFAULT_PORTS: u8 = 4;
+const MAX_POWER: u8 = 33;
+/// Baud rates the reader supports, the first being what it's set to from the factory
+const BAUD_RATES: &[usize] = &[115200, 38400];
+const DEFAULT_ADDRESS: u8 = 1;
+/// Address which every reader responds to, so it can't be used for one of several on a bus
+const BROADCAST: u8 = 0xFF;
+/// First byte of every frame
+const START: u8 = 0xA0;
+const CMD_WRITE: u8 = 0x82;
+const CMD_LOCK: u8 = 0x83;
+/// Real-time inventory in a given session and target, which the invelion crate doesn't have
+const CMD_SESSION_INVENTORY: u8 = 0x8B;
+
+/// Checksum of a frame, which makes its bytes sum to zero.
+pub(super) fn checksum(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg()
+}
+
+/// A command frame, for commands which the invelion crate doesn't have.
+pub(super) fn frame(address: u8, command: u8, data: &[u8]) -> Vec<u8> {
+    let mut pkt = vec![START, data.len() as u8 + 3, address, command];
+    pkt.extend(data);
+    pkt.push(checksum(&pkt));
+    pkt
+}
+
+/// Wait for the reply to `command` from the reader at `address`, returning the data after the
+/// command byte and discarding any other frames.
+fn receive(port: &mut impl Read, address: u8, command: u8) -> Result<Vec<u8>, failure::Error> {
+    let mut byte = [0u8];
+    loop {
+        port.read_exact(&mut byte).map_err(|err| match err.kind() {
+            io::ErrorKind::TimedOut => format_err!("No reply from reader {}", address),
+            _ => err.into(),
+        })?;
+        if byte[0] != START {
+            continue;
+        }
+        port.read_exact(&mut byte)?;
+        let mut pkt = vec![START, byte[0]];
+        pkt.resize(2 + byte[0] as usize, 0);
+        port.read_exact(&mut pkt[2..])?;
+        let (body, check) = pkt.split_at(pkt.len() - 1);
+        if body.len() < 4 || checksum(body) != check[0] {
+            debug!("Discarding bad frame {:?}", pkt);
+        } else if body[2] == address && body[3] == command {
+            return Ok(body[4..].to_vec());
+        }
+    }
+}
+
+/// The error for a response code from the reader.
+fn status_error(status: u8) -> Error {
+    match ResponseCode::try_from(status) {
+        Ok(code) => Error::Protocol(code),
+        Err(err) => err.into(),
+    }
+}
+
+/// Inventory tags in a session and target, returning each tag read with the antenna number the
+/// reader gives.
+fn session_inventory(
+    port: &mut (impl Read + Write),
+    address: u8,
+    session: Session,
+    target: bool,
+) -> Result<Vec<ScanResult>, failure::Error> {
+    port.write_all(&frame(
+        address,
+        CMD_SESSION_INVENTORY,
+        &[session as u8, target as u8, 1],
+    ))?;
+    let mut result = Vec::new();
+    loop {
+        let reply = receive(port, address, CMD_SESSION_INVENTORY)?;
+        match reply.len() {
+            1 => return Err(status_error(reply[0]).into()),
+            // Antenna, read rate and total read count end the round
+            7 => return Ok(result),
+            // Frequency and antenna, PC, EPC and RSSI
+            len if len >= 4 => {
+                let mut res = ScanResult::from_read(reply[3..len - 1].to_vec());
+                res.pc = to_word(&reply[1..]);
+                res.rssi = Some(rssi(reply[len - 1]));
+                res.antenna = Some(reply[0] & 0x03);
+                result.push(res);
+            }
+            _ => bail!("Invalid reply from reader {}: {:?}", address, reply),
+        }
+    }
+}
+
+/// RSSI in dBm, converted the same way as the invelion crate does for real-time inventory.
+fn rssi(value: u8) -> i8 {
+    if value > 89 {
+        (i16::from(value) - 129) as i8
+    } else {
+        (i16::from(value) - 130) as i8
+    }
+}
+
+/// Antenna ports and output power, given after the serial port as comma-separated options
+struct InvelionOptions {
+    port: String,
+    baud: usize,
+    /// Addresses of the readers on the port, if it's an RS-485 bus with more than one
+    addresses: Vec<u8>,
+    /// Number of antenna ports each reader has
+    ports: u8,
+    /// Antennas to use, with their output power if it should be set
+    antennas: Vec<(u8, Option<u8>)>,
+    /// Output power for antennas which don't have their own
+    power: Option<u8>,
+}
+
+fn parse_power(value: &str) -> Result<u8, failure::Error> {
+    let power = value.parse()?;
+    if power > MAX_POWER {
+        bail!("Output power must be at most {} dBm", MAX_POWER);
+    }
+    Ok(power)
+}
+
+impl InvelionOptions {
+    fn parse(spec: &str) -> Result<InvelionOptions, failure::Error> {
+        let mut parts = spec.split(',');
+        let mut options = InvelionOptions {
+            port: parts.next().unwrap_or("").to_string(),
+            baud: BAUD_RATES[0],
+            addresses: Vec::new(),
+            ports: DEFAULT_PORTS,
+            antennas: Vec::new(),
+            power: None,
+        };
+        for opt in parts {
+            let (key, value) = match opt.find('=') {
+                Some(i) => (&opt[..i], &opt[i + 1..]),
+                None => bail!("Invelion options must be in the form key=value"),
+            };
+            match key {
+                "baud" => options.baud = parse_baud(value, BAUD_RATES)?,
+                "address" => {
+                    let address = value.parse()?;
+                    if address == BROADCAST {
+                        bail!("Reader address must be below {}", BROADCAST);
+                    }
+                    if options.addresses.contains(&address) {
+                        bail!("Reader address {} is given more than once", address);
+                    }
+                    options.addresses.push(address);
+                }
+                "ports" => options.ports = value.parse()?,
+                "antenna" => {
+                    let mut fields = value.splitn(2, ':');
+                    let antenna = fields.next().unwrap_or("").parse()?;
+                    let power = fields.next().map(parse_power).transpose()?;
+                    options.antennas.push((antenna, power));
+                }
+                "power" => options.power = Some(parse_power(value)?),
+                _ => bail!("Unknown invelion option '{}'", key),
+            }
+        }
+        if !(1..=8).contains(&options.ports) {
+            bail!("Readers have between 1 and 8 antenna ports");
+        }
+        if options.addresses.is_empty() {
+            options.addresses.push(DEFAULT_ADDRESS);
+        }
+        let antennas = options.addresses.len() * options.ports as usize;
+        if antennas > usize::from(u8::MAX) {
+            bail!(
+                "Too many readers, they have {} antennas between them",
+                antennas
+            );
+        }
+        if let Some((antenna, _)) = options
+            .antennas
+            .iter()
+            .find(|(antenna, _)| usize::from(*antenna) >= antennas)
+        {
+            match options.addresses.len() {
+                1 => bail!(
+                    "Antenna {} doesn't exist on a {} port reader",
+                    antenna,
+                    options.ports
+                ),
+                readers => bail!(
+                    "Antenna {} doesn't exist on {} readers with {} ports each",
+                    antenna,
+                    readers,
+                    options.ports
+                ),
+            }
+        }
+        if options.antennas.is_empty() {
+            options.antennas = (0..antennas as u8).map(|antenna| (antenna, None)).collect();
+        }
+        options.antennas.sort();
+        options.antennas.dedup_by_key(|(antenna, _)| *antenna);
+        Ok(options)
+    }
+}
+
+/// Invelion (and similar Impinj R2000-based) reader driver
+///
+/// Several readers on an RS-485 bus are treated as one, with the antennas of each numbered on
+/// from the one before.
+pub(crate) struct Invelion {
+    /// Readers in the order their addresses were given
+    readers: Vec<invelion::Reader>,
+    /// Address of each reader
+    addresses: Vec<u8>,
+    /// The serial port, for commands which the invelion crate doesn't have
+    handle: PortHandle,
+    /// Number of antenna ports each reader has
+    ports: u8,
+    /// Antennas given on the command line, used unless the scan settings choose others
+    configured: Vec<u8>,
+    /// Antennas to inventory on
+    enabled: Vec<u8>,
+    /// Index in `enabled` of the antenna used for the next detailed scan
+    detail_antenna: usize,
+    /// Session and target to inventory, if the reader's own defaults shouldn't be used
+    session: Option<(Session, Target)>,
+    /// Whether the last round inventoried target B, for alternating between A and B
+    last_target: bool,
+}
+
+impl Invelion {
+    pub fn new(spec: &str) -> Result<Invelion, failure::Error> {
+        let options = InvelionOptions::parse(spec)?;
+        // Opened first so the port can be shared between readers and set to another baud rate
+        let mut handle = PortHandle::open(&options.port, options.baud)?;
+        let mut readers = Vec::new();
+        for address in &options.addresses {
+            readers.push(invelion::Reader::new(
+                &options.port,
+                *address,
+                options.ports,
+            )?);
+            handle.share()?;
+        }
+        if options.baud != BAUD_RATES[0] {
+            handle.port()?;
+        }
+
+        let powers: Vec<(u8, u8)> = options
+            .antennas
+            .iter()
+            .filter_map(|(antenna, power)| power.or(options.power).map(|power| (*antenna, power)))
+            .collect();
+        for (i, reader) in readers.iter_mut().enumerate() {
+            let first = i as u8 * options.ports;
+            let powers: Vec<(u8, u8)> = powers
+                .iter()
+                .filter(|(antenna, _)| (first..first + options.ports).contains(antenna))
+                .map(|(antenna, power)| (antenna - first, *power))
+                .collect();
+            if powers.is_empty() {
+                continue;
+            }
+            // The reader sets all ports at once, so keep the power of any we aren't changing
+            let mut output_power = reader.get_output_power()?;
+            for (antenna, power) in powers {
+                output_power[antenna as usize] = power;
+            }
+            reader.set_output_power(&output_power)?;
+        }
+
+        let configured: Vec<u8> = options
+            .antennas
+            .iter()
+            .map(|(antenna, _)| *antenna)
+            .collect();
+        Ok(Invelion {
+            readers,
+            addresses: options.addresses,
+            handle,
+            ports: options.ports,
+            enabled: configured.to_owned(),
+            configured,
+            detail_antenna: 0,
+            session: None,
+            last_target: true,
+        })
+    }
+
+    /// The reader an antenna belongs to, and its number on that reader.
+    fn antenna(&mut self, antenna: u8) -> (&mut invelion::Reader, u8) {
+        let ports = self.ports;
+        (
+            &mut self.readers[(antenna / ports) as usize],
+            antenna % ports,
+        )
+    }
+
+    /// The number of an antenna on the reader at `index`, as seen by the rest of epcexplorer.
+    fn antenna_number(&self, index: usize, antenna: u8) -> u8 {
+        index as u8 * self.ports + antenna
+    }
+
+    /// Send a write or lock command to the tag with this EPC, trying each reader until one has
+    /// seen it.
+    ///
+    /// The reader's EPC match makes it access only that tag, rather than every tag in the field.
+    fn access(
+        &mut self,
+        epc: &[u8],
+        command: u8,
+        data: &[u8],
+        password: u32,
+    ) -> Result<(), failure::Error> {
+        let mut error = None;
+        for (reader, address) in self.readers.iter_mut().zip(&self.addresses) {
+            reader.set_epc_match(epc)?;
+            let port = self.handle.port()?;
+            port.write_all(&frame(*address, command, data))?;
+            let result = access_result(port, *address, command);
+            reader.set_epc_match(&[])?;
+            match result? {
+                Ok(()) => return Ok(()),
+                Err(err) => error = Some(tag_error(err, password)),
+            }
+        }
+        Err(error.unwrap_or_else(|| format_err!("No response from tag {}", hex::encode_upper(epc))))
+    }
+}
+
+/// Read the replies to a tag access command, one from each tag it was sent to. The outer error
+/// is from talking to the reader, and the inner one from the tags.
+fn access_result(
+    port: &mut impl Read,
+    address: u8,
+    command: u8,
+) -> Result<Result<(), Error>, failure::Error> {
+    let mut result = Err(Error::Protocol(ResponseCode::NoTagError));
+    let mut replies = 0;
+    loop {
+        let reply = receive(port, address, command)?;
+        // A reply with only a response code means no tag was accessed
+        if let [status] = reply[..] {
+            return Ok(Err(status_error(status)));
+        }
+        // Tag count, PC + EPC + CRC length and data, response code, antenna and access count
+        let status = reply
+            .get(2)
+            .and_then(|len| reply.get(3 + *len as usize))
+            .cloned();
+        match status {
+            Some(status) if status == ResponseCode::Success as u8 => result = Ok(()),
+            Some(status) if result.is_err() => result = Err(status_error(status)),
+            Some(_) => {}
+            None => bail!("Invalid reply from reader {}: {:?}", address, reply),
+        }
+        replies += 1;
+        if replies >= to_word(&reply).map_or(1, usize::from) {
+            return Ok(result);
+        }
+    }
+}
+
+fn memory_bank(bank: MemoryBank) -> protocol::MemoryBank {
+    match bank {
+        MemoryBank::Reserved => protocol::MemoryBank::Reserved,
+        MemoryBank::EPC => protocol::MemoryBank::EPC,
+        MemoryBank::TID => protocol::MemoryBank::TID,
+        MemoryBank::User => protocol::MemoryBank::User,
+    }
+}
+
+/// Convert a failed tag operation into an `AccessError` if the tag refused access.
+///
+/// The invelion crate doesn't keep the response code of failed commands, only its name.
+fn tag_error(err: invelion::error::Error, password: u32) -> failure::Error {
+    if err.to_string().contains("AccessFailError") {
+        AccessError::for_password(password).into()
+    } else {
+        err.into()
+    }
+}
+
+impl Reader for Invelion {
+    /// The reader's EPC match only restricts which tag is read, written or locked, not which
+    /// tags are inventoried, so it's used for tag access and Select masks are applied in
+    /// software.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            rssi: true,
+            antennas: true,
+            select: false,
+            session: true,
+            max_power: Some(MAX_POWER),
+        }
+    }
+
+    fn antennas(&mut self) -> Result<Vec<u8>, failure::Error> {
+        Ok((0..self.readers.len() as u8 * self.ports).collect())
+    }
+
+    /// The firmware versions of all readers, and the temperature of the hottest.
+    fn health(&mut self) -> Result<ReaderHealth, failure::Error> {
+        let mut firmware: Vec<String> = Vec::new();
+        let mut temperature = None;
+        for reader in self.readers.iter_mut() {
+            let (major, minor) = reader.get_version()?;
+            let version = format!("{}.{}", major, minor);
+            if !firmware.contains(&version) {
+                firmware.push(version);
+            }
+            temperature = temperature.max(Some(reader.get_temperature()?));
+        }
+        Ok(ReaderHealth {
+            firmware: Some(firmware.join("/")),
+            temperature,
+        })
+    }
+
+    /// The reader accepts antennas, power, and a session and target. Power is read back to show
+    /// what was actually set.
+    ///
+    /// If no antennas are given, the ones from the command line are used. Power applies to all
+    /// ports, replacing any per-port power from the command line. Session and target are only
+    /// used together, and the reader chooses Q itself.
+    fn configure(&mut self, settings: &ScanSettings) -> Result<ScanSettings, failure::Error> {
+        let antennas = self.antennas()?;
+        let enabled: Vec<u8> = match &settings.antennas {
+            Some(enabled) => enabled
+                .iter()
+                .cloned()
+                .filter(|antenna| antennas.contains(antenna))
+                .collect(),
+            None => self.configured.to_owned(),
+        };
+        if enabled.is_empty() {
+            bail!("At least one antenna must be enabled");
+        }
+        self.enabled = enabled;
+        self.detail_antenna = 0;
+
+        if let Some(power) = settings.power {
+            for reader in self.readers.iter_mut() {
+                reader.set_output_power(&vec![power; self.ports as usize])?;
+            }
+        }
+        let power = match settings.power {
+            Some(_) => {
+                let (reader, antenna) = self.antenna(self.enabled[0]);
+                reader.get_output_power()?.get(antenna as usize).cloned()
+            }
+            None => None,
+        };
+        self.session = match (settings.session, settings.target) {
+            (Some(session), Some(target)) => Some((session, target)),
+            _ => None,
+        };
+        Ok(ScanSettings {
+            detailed_scan: settings.detailed_scan,
+            antennas: settings.antennas.as_ref().map(|_| self.enabled.to_owned()),
+            power,
+            session: self.session.map(|(session, _)| session),
+            target: self.session.map(|(_, target)| target),
+            ..ScanSettings::default()
+        })
+    }
+
+    fn inventory(&mut self) -> Result<Vec<ScanResult>, failure::Error> {
+        let session = self.session.map(|(session, target)| {
+            let target = match target {
+                Target::A => false,
+                Target::B => true,
+                Target::AB => !self.last_target,
+            };
+            self.last_target = target;
+            (session, target)
+        });
+        let mut result = Vec::new();
+        for i in self.enabled.clone() {
+            let index = (i / self.ports) as usize;
+            let (reader, antenna) = self.antenna(i);
+            reader.set_work_antenna(antenna)?;
+            if let Some((session, target)) = session {
+                let address = self.addresses[index];
+                let tags = session_inventory(self.handle.port()?, address, session, target)?;
+                for mut res in tags {
+                    res.antenna = res
+                        .antenna
+                        .map(|antenna| self.antenna_number(index, antenna));
+                    result.push(res);
+                }
+                continue;
+            }
+            let inv = reader.real_time_inventory(255)?;
+            for item in inv.items.iter() {
+                let mut res = ScanResult::from_read(item.epc.to_owned());
+                res.pc = to_word(&item.pc);
+                res.rssi = Some(item.rssi);
+                res.antenna = Some(self.antenna_number(index, item.antenna));
+                result.push(res);
+            }
+        }
+        Ok(result)
+    }
+
+    /// The reader reads from every tag in the field, so this discards all but the one we want.
+    ///
+    /// With several readers, each is tried until one has seen the tag.
+    fn read_memory(
+        &mut self,
+        epc: &[u8],
+        bank: MemoryBank,
+        start: u8,
+        words: u8,
+        password: u32,
+    ) -> Result<Vec<u8>, failure::Error> {
+        let mut error = None;
+        for reader in self.readers.iter_mut() {
+            match reader.read(memory_bank(bank), &password.to_be_bytes(), start, words) {
+                Ok(data) => {
+                    if let Some(response) = data.into_iter().find(|response| response.epc == epc) {
+                        return Ok(response.data);
+                    }
+                }
+                Err(err) => error = Some(tag_error(err, password)),
+            }
+        }
+        Err(error.unwrap_or_else(|| format_err!("No response from tag {}", hex::encode_upper(epc))))
+    }
+
+    fn write_memory(
+        &mut self,
+        epc: &[u8],
+        bank: MemoryBank,
+        start: u8,
+        data: &[u8],
+        password: u32,
+    ) -> Result<(), failure::Error> {
+        if data.is_empty() || !data.len().is_multiple_of(2) {
+            bail!("Data must be a whole number of words");
+        }
+        let mut command = password.to_be_bytes().to_vec();
+        command.extend(&[memory_bank(bank) as u8, start, (data.len() / 2) as u8]);
+        command.extend(data);
+        self.access(epc, CMD_WRITE, &command, password)
+    }
+
+    fn lock(
+        &mut self,
+        epc: &[u8],
+        target: LockTarget,
+        action: LockAction,
+        password: u32,
+    ) -> Result<(), failure::Error> {
+        let region = match target {
+            LockTarget::User => 0x01,
+            LockTarget::TID => 0x02,
+            LockTarget::EPC => 0x03,
+            LockTarget::AccessPassword => 0x04,
+            LockTarget::KillPassword => 0x05,
+        };
+        let lock_type = match action {
+            LockAction::Unlock => 0x00,
+            LockAction::Lock => 0x01,
+            LockAction::PermaUnlock => 0x02,
+            LockAction::PermaLock => 0x03,
+        };
+        let mut command = password.to_be_bytes().to_vec();
+        command.extend(&[region, lock_type]);
+        self.access(epc, CMD_LOCK, &command, password)
+    }
+
+    /// Read the TID of all tags on one antenna, cycling through antennas on each call, along
+    /// with XPC_W1 from any tags which have one.
+    ///
+    /// Tags are all read at once, so they're all accessed with the default password.
+    fn details(
+        &mut self,
+        tags: &[ScanResult],
+        passwords: &Passwords,
+    ) -> Result<Vec<ScanResult>, failure::Error> {
+        let antenna = self.enabled[self.detail_antenna % self.enabled.len()];
+        self.detail_antenna = (self.detail_antenna + 1) % self.enabled.len();
+
+        let index = (antenna / self.ports) as usize;
+        let (reader, antenna) = self.antenna(antenna);
+        reader.set_work_antenna(antenna)?;
+        let data = reader
+            .read(
+                protocol::MemoryBank::TID,
+                &passwords.default.to_be_bytes(),
+                0,
+                2,
+            )
+            .map_err(|err| tag_error(err, passwords.default))?;
+        let mut result: Vec<ScanResult> = data
+            .iter()
+            .map(|response| {
+                let mut res = ScanResult::from_epc(response.epc.to_owned());
+                res.tid = parse_tid(&response.data);
+                res.antenna = Some(self.antenna_number(index, response.antenna));
+                res
+            })
+            .collect();
+        for tag in tags {
+            if let Some(xpc_w1) = read_xpc_w1(self, tag, passwords.get(&tag.epc)) {
+                let mut res = ScanResult::from_epc(tag.epc.to_owned());
+                res.xpc_w1 = Some(xpc_w1);
+                result.push(res);
+            }
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,95 @@
+//! Reader drivers
+//!
+//! To add a new driver, implement `rfid::Reader` for it and add it to `DRIVERS` and `open`.
+use crate::rfid::Reader;
+use failure::bail;
+use log::info;
+use std::path::Path;
+
+pub(crate) mod detect;
+mod invelion;
+mod port;
+mod replay;
+mod ru5102;
+mod sim;
+mod usb;
+
+/// Names of the available drivers, as accepted on the command line
+pub(crate) const DRIVERS: &[&str] = &["ru5102", "invelion", "sim", "replay"];
+
+/// Drivers whose PORT starts with a serial port, which can be given as `usb:VID:PID[:SERIAL]`
+const SERIAL_DRIVERS: &[&str] = &["ru5102", "invelion"];
+
+/// Connect to a reader on `port` using the named driver.
+fn open(driver: &str, port: &str) -> Result<Box<dyn Reader>, failure::Error> {
+    Ok(match driver {
+        "ru5102" => Box::new(ru5102::RU5102::new(port)?),
+        "invelion" => Box::new(invelion::Invelion::new(port)?),
+        "sim" => Box::new(sim::Sim::new(port)?),
+        "replay" => Box::new(replay::Replay::new(port)?),
+        _ => {
+            bail!("Invalid reader type (shouldn't happen)");
+        }
+    })
+}
+
+/// Opens a reader, and opens it again after the connection is lost.
+pub(crate) struct Connector {
+    driver: String,
+    port: String,
+    /// The USB serial adapter the reader was first found on, to look for if its serial port
+    /// goes away
+    usb: Option<usb::UsbDevice>,
+}
+
+impl Connector {
+    pub fn new(driver: &str, port: &str) -> Result<Connector, failure::Error> {
+        let mut connector = Connector {
+            driver: driver.to_string(),
+            port: port.to_string(),
+            usb: None,
+        };
+        if let Some(device) = connector.serial_port() {
+            connector.usb = match device.strip_prefix("usb:") {
+                Some(_) => Some(usb::UsbDevice::parse(device)?),
+                None => usb::identify(device),
+            };
+        }
+        Ok(connector)
+    }
+
+    /// The serial port part of `port`, for drivers which use one.
+    fn serial_port(&self) -> Option<&str> {
+        if !SERIAL_DRIVERS.contains(&self.driver.as_str()) {
+            return None;
+        }
+        self.port.split(',').next()
+    }
+
+    /// Whether the reader's serial port is still there, or true if it doesn't have one.
+    pub fn present(&self) -> bool {
+        match self.serial_port() {
+            Some(device) if !device.starts_with("usb:") => Path::new(device).exists(),
+            _ => true,
+        }
+    }
+
+    /// The driver's PORT, with the serial port replaced by the USB adapter's current one if it
+    /// has moved.
+    fn resolve(&self) -> Result<String, failure::Error> {
+        let device = match (self.serial_port(), &self.usb) {
+            (Some(device), Some(usb)) if device.starts_with("usb:") || !self.present() => {
+                let found = usb::find(usb)?;
+                info!("Found {} at {}", usb, found);
+                found
+            }
+            _ => return Ok(self.port.to_owned()),
+        };
+        let options = &self.port[self.port.find(',').unwrap_or(self.port.len())..];
+        Ok(format!("{}{}", device, options))
+    }
+
+    pub fn open(&self) -> Result<Box<dyn Reader>, failure::Error> {
+        open(&self.driver, &self.resolve()?)
+    }
+}
@@ -0,0 +1,122 @@
+//! Serial ports shared with the driver crates.
+//!
+//! The driver crates open their serial port at a fixed baud rate and take exclusive use of it.
+//! A handle opened on the port beforehand can end the exclusive use, so another reader on the
+//! same RS-485 bus can be opened on it, and so the port can be opened again to change its baud
+//! rate or send commands which the crates don't have.
+use failure::{bail, format_err};
+use serial::SerialPort;
+use std::time::Duration;
+
+/// How long to wait for a reply to a command sent directly to the reader
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Parse a baud rate, which must be one of `supported`.
+pub(crate) fn parse_baud(value: &str, supported: &[usize]) -> Result<usize, failure::Error> {
+    let baud = value.parse()?;
+    if !supported.contains(&baud) {
+        let mut rates = supported.to_vec();
+        rates.sort_unstable();
+        let rates: Vec<String> = rates.iter().map(|rate| rate.to_string()).collect();
+        bail!("Baud rate must be one of {}", rates.join(", "));
+    }
+    Ok(baud)
+}
+
+/// Set a port to talk to a reader at `baud`, with 8 data bits, no parity and one stop bit.
+pub(crate) fn configure(
+    port: &mut serial::SystemPort,
+    baud: usize,
+    timeout: Duration,
+) -> Result<(), failure::Error> {
+    port.reconfigure(&|settings| {
+        settings.set_baud_rate(serial::BaudRate::from_speed(baud))?;
+        settings.set_char_size(serial::Bits8);
+        settings.set_parity(serial::ParityNone);
+        settings.set_stop_bits(serial::Stop1);
+        settings.set_flow_control(serial::FlowNone);
+        Ok(())
+    })?;
+    port.set_timeout(timeout)?;
+    Ok(())
+}
+
+/// A handle on a serial port, to be opened before the driver opens it
+pub(crate) struct PortHandle {
+    device: String,
+    /// Kept open so the port can be shared after the driver has taken exclusive use of it
+    #[cfg(unix)]
+    file: std::fs::File,
+    baud: usize,
+    /// The port opened alongside the driver's, once it's needed
+    port: Option<serial::SystemPort>,
+}
+
+impl PortHandle {
+    #[cfg(unix)]
+    pub fn open(device: &str, baud: usize) -> Result<PortHandle, failure::Error> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(device)
+            .map_err(|e| format_err!("Unable to open serial port {}: {}", device, e))?;
+        Ok(PortHandle {
+            device: device.to_string(),
+            file,
+            baud,
+            port: None,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(device: &str, baud: usize) -> Result<PortHandle, failure::Error> {
+        Ok(PortHandle {
+            device: device.to_string(),
+            baud,
+            port: None,
+        })
+    }
+
+    /// Let the port be opened again after a driver has taken exclusive use of it.
+    #[cfg(unix)]
+    pub fn share(&self) -> Result<(), failure::Error> {
+        use std::os::unix::io::AsRawFd;
+
+        ioctl_rs::tiocnxcl(self.file.as_raw_fd())
+            .map_err(|e| format_err!("Unable to share serial port {}: {}", self.device, e))
+    }
+
+    /// Serial ports can't be shared outside Unix, so they can only be opened once.
+    #[cfg(not(unix))]
+    pub fn share(&self) -> Result<(), failure::Error> {
+        bail!(
+            "Serial port {} can't be shared on this platform, so it can only have one reader \
+             and its baud rate can't be changed",
+            self.device
+        )
+    }
+
+    /// The port, opened alongside the driver's at the handle's baud rate.
+    ///
+    /// The baud rate applies to the driver's port too, so this is called once the driver has
+    /// opened the port to set a baud rate other than the driver's.
+    pub fn port(&mut self) -> Result<&mut serial::SystemPort, failure::Error> {
+        let port = match self.port.take() {
+            Some(port) => port,
+            None => {
+                self.share()?;
+                let mut port = serial::open(&self.device).map_err(|e| {
+                    format_err!("Unable to open serial port {}: {}", self.device, e)
+                })?;
+                // Opening the port took exclusive use of it again
+                self.share()?;
+                configure(&mut port, self.baud, TIMEOUT)?;
+                port
+            }
+        };
+        Ok(self.port.insert(port))
+    }
+}
@@ -0,0 +1,194 @@
+//! Replay a session recorded with `--record`.
+//!
+//! The PORT argument is the path to the recording, optionally followed by `,speed=N` to replay
+//! N times faster than it was recorded, and `,reader=N` to replay only what the Nth reader in the
+//! recording saw, counting from 0.
+use crate::record::{self, RecordEntry};
+use crate::rfid::{Capabilities, MemoryBank, Passwords, Reader, ScanResult};
+use failure::{bail, format_err};
+use log::info;
+use std::cmp;
+use std::mem;
+use std::thread;
+use std::time;
+
+/// Longest time to block in `inventory`, so that settings changes are still picked up
+const MAX_WAIT: time::Duration = time::Duration::from_millis(250);
+
+/// Replay driver
+pub(crate) struct Replay {
+    entries: Vec<RecordEntry>,
+    position: usize,
+    /// Details of the tags read in the last call to `inventory`, for `details` to return
+    details: Vec<ScanResult>,
+    speed: f64,
+    start: time::Instant,
+    finished: bool,
+}
+
+impl Replay {
+    pub fn new(spec: &str) -> Result<Replay, failure::Error> {
+        let mut parts = spec.split(',');
+        let path = parts.next().unwrap_or("");
+        let mut speed = 1.0;
+        let mut reader: Option<usize> = None;
+        for opt in parts {
+            match opt.find('=') {
+                Some(i) if &opt[..i] == "speed" => speed = opt[i + 1..].parse()?,
+                Some(i) if &opt[..i] == "reader" => reader = Some(opt[i + 1..].parse()?),
+                _ => bail!("Unknown replay option '{}'", opt),
+            }
+        }
+        if speed <= 0.0 {
+            bail!("Replay speed must be positive");
+        }
+        let mut entries = record::load(path)?;
+        if let Some(reader) = reader {
+            entries.retain(|entry| entry.reader == reader);
+        }
+        Ok(Replay {
+            entries,
+            position: 0,
+            details: Vec::new(),
+            speed,
+            start: time::Instant::now(),
+            finished: false,
+        })
+    }
+
+    /// Time since the start of the replay at which an entry is due.
+    fn due(&self, entry: &RecordEntry) -> time::Duration {
+        time::Duration::from_millis(entry.time).div_f64(self.speed)
+    }
+}
+
+impl Reader for Replay {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            rssi: true,
+            antennas: true,
+            select: false,
+            session: false,
+            max_power: None,
+        }
+    }
+
+    fn antennas(&mut self) -> Result<Vec<u8>, failure::Error> {
+        let mut antennas: Vec<u8> = self.entries.iter().filter_map(|e| e.antenna).collect();
+        antennas.sort();
+        antennas.dedup();
+        Ok(antennas)
+    }
+
+    fn inventory(&mut self) -> Result<Vec<ScanResult>, failure::Error> {
+        let next = match self.entries.get(self.position) {
+            Some(entry) => self.due(entry),
+            None => {
+                if !self.finished {
+                    info!("Replay finished");
+                    self.finished = true;
+                }
+                thread::sleep(MAX_WAIT);
+                return Ok(vec![]);
+            }
+        };
+        let elapsed = self.start.elapsed();
+        if next > elapsed {
+            thread::sleep(cmp::min(next - elapsed, MAX_WAIT));
+        }
+
+        let elapsed = self.start.elapsed();
+        let mut result = Vec::new();
+        self.details.clear();
+        while let Some(entry) = self.entries.get(self.position) {
+            if self.due(entry) > elapsed {
+                break;
+            }
+            if entry.read {
+                result.push(entry.to_result()?);
+            } else {
+                self.details.push(entry.to_result()?);
+            }
+            self.position += 1;
+        }
+        Ok(result)
+    }
+
+    fn read_memory(
+        &mut self,
+        _epc: &[u8],
+        bank: MemoryBank,
+        _start: u8,
+        _words: u8,
+        _password: u32,
+    ) -> Result<Vec<u8>, failure::Error> {
+        Err(format_err!("Can't read {:?} bank from a recording", bank))
+    }
+
+    /// Detailed scan results are part of the recording, so are returned as they come due, rather
+    /// than read from the tags.
+    fn details(
+        &mut self,
+        _tags: &[ScanResult],
+        _passwords: &Passwords,
+    ) -> Result<Vec<ScanResult>, failure::Error> {
+        Ok(mem::take(&mut self.details))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    /// Two readers' reads and a detailed scan result, with the last line from before reads and
+    /// readers were recorded
+    const RECORDING: &str = r#"{"time":0,"epc":"300833B2DDD9014000000001","read":true,"pc":12288,"tid":null,"xtid_header":null,"serial":null,"rssi":-60,"antenna":0,"reader":0}
+{"time":0,"epc":"300833B2DDD9014000000002","read":true,"pc":12288,"tid":null,"xtid_header":null,"serial":null,"rssi":-48,"antenna":1,"reader":1}
+
+{"time":0,"epc":"300833B2DDD9014000000002","read":false,"tid":{"xtid":false,"security":false,"file":false,"mdid":2,"tmid":384},"xtid_header":null,"serial":null,"rssi":null,"antenna":1,"reader":1}
+{"time":60000,"epc":"300833B2DDD9014000000003","tid":null,"xtid_header":null,"serial":null,"rssi":-70,"antenna":2}
+"#;
+
+    /// Replay `RECORDING`, written to a file for the test called `name`.
+    fn replay(name: &str, options: &str) -> Replay {
+        let path = env::temp_dir().join(format!("epcexplorer-{}-{}.jsonl", name, process::id()));
+        fs::write(&path, RECORDING).unwrap();
+        let replay = Replay::new(&format!("{}{}", path.display(), options));
+        fs::remove_file(&path).unwrap();
+        replay.unwrap()
+    }
+
+    #[test]
+    fn replays_recordings() {
+        let mut replay = replay("all", "");
+        assert_eq!(replay.entries.len(), 4);
+        assert!(replay.entries[3].read);
+        assert_eq!(replay.antennas().unwrap(), vec![0, 1, 2]);
+
+        // Only the entries due at the start are replayed
+        let reads = replay.inventory().unwrap();
+        assert_eq!(reads.len(), 2);
+        assert_eq!(
+            reads[1].epc,
+            hex::decode("300833B2DDD9014000000002").unwrap()
+        );
+        assert_eq!(reads[1].rssi, Some(-48));
+        assert_eq!(reads[1].reader, 1);
+        let details = replay.details(&reads, &Passwords::default()).unwrap();
+        assert_eq!(details.len(), 1);
+        assert!(!details[0].read);
+        assert_eq!(details[0].tid.as_ref().map(|tid| tid.tmid), Some(384));
+    }
+
+    #[test]
+    fn replays_one_reader() {
+        let mut replay = replay("reader", ",reader=1,speed=2");
+        assert_eq!(replay.entries.len(), 2);
+        let reads = replay.inventory().unwrap();
+        assert_eq!(reads.len(), 1);
+        assert_eq!(reads[0].antenna, Some(1));
+    }
+}
@@ -0,0 +1,278 @@
+use super::port::{parse_baud, PortHandle};
+use crate::rfid::{
+    AccessError, Capabilities, LockAction, LockTarget, MemoryBank, Reader, ReaderHealth,
+    ScanResult, ScanSettings,
+};
+use ::ru5102::error::Error;
+use ::ru5102::ResponseStatus;
+use failure::{bail, format_err};
+use log::debug;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+/// Baud rates the reader supports, the first being what it's set to from the factory
+const BAUD_RATES: &[usize] = &[57600, 9600, 19200, 38400, 115200];
+/// The only address the ru5102 crate talks to
+const ADDRESS: u8 = 0;
+const CMD_LOCK: u8 = 0x06;
+const CMD_READER_INFORMATION: u8 = 0x21;
+const CMD_SET_POWER: u8 = 0x2F;
+const MAX_POWER: u8 = 30;
+
+/// CRC used by the RU5102 protocol (CRC-16/MCRF4XX)
+pub(super) fn crc(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in data {
+        crc ^= u16::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// A command frame, for commands which the ru5102 crate doesn't have.
+pub(super) fn frame(address: u8, command: u8, data: &[u8]) -> Vec<u8> {
+    let mut pkt = vec![data.len() as u8 + 4, address, command];
+    pkt.extend(data);
+    pkt.extend(&crc(&pkt).to_le_bytes());
+    pkt
+}
+
+/// Wait for the reply to `command`, returning its status and data and discarding any other
+/// frames.
+fn receive(port: &mut impl Read, command: u8) -> Result<(u8, Vec<u8>), failure::Error> {
+    loop {
+        let mut len = [0u8];
+        port.read_exact(&mut len).map_err(|err| match err.kind() {
+            io::ErrorKind::TimedOut => format_err!("No reply from reader"),
+            _ => err.into(),
+        })?;
+        let mut pkt = vec![len[0]; 1 + len[0] as usize];
+        port.read_exact(&mut pkt[1..])?;
+        let (body, check) = pkt.split_at(pkt.len().saturating_sub(2));
+        if body.len() < 4 || crc(body).to_le_bytes() != check {
+            debug!("Discarding bad frame {:?}", pkt);
+        } else if body[2] == command {
+            return Ok((body[3], body[4..].to_vec()));
+        }
+    }
+}
+
+/// CH-RU5102 driver
+///
+/// The ru5102 crate only talks to the reader at address 0, so readers can't share a bus.
+pub(crate) struct RU5102 {
+    reader: ::ru5102::Reader,
+    /// The serial port, for commands which the ru5102 crate doesn't have
+    handle: PortHandle,
+}
+
+impl RU5102 {
+    /// Connect to a reader on `spec`, a serial port optionally followed by `,baud=N`.
+    pub fn new(spec: &str) -> Result<RU5102, failure::Error> {
+        let mut parts = spec.split(',');
+        let port = parts.next().unwrap_or("");
+        let mut baud = BAUD_RATES[0];
+        for opt in parts {
+            match opt.strip_prefix("baud=") {
+                Some(value) => baud = parse_baud(value, BAUD_RATES)?,
+                None => bail!("Unknown ru5102 option '{}'", opt),
+            }
+        }
+        // Opened first so the port can be set to another baud rate once the reader has it
+        let mut handle = PortHandle::open(port, baud)?;
+        let reader = ::ru5102::Reader::new(port)?;
+        if baud != BAUD_RATES[0] {
+            handle.port()?;
+        }
+        Ok(RU5102 { reader, handle })
+    }
+}
+
+impl RU5102 {
+    /// Send a command the ru5102 crate doesn't have, returning the data of its reply.
+    fn command(&mut self, command: u8, data: &[u8]) -> Result<Vec<u8>, failure::Error> {
+        let port = self.handle.port()?;
+        port.write_all(&frame(ADDRESS, command, data))?;
+        let (status, data) = receive(port, command)?;
+        match ResponseStatus::try_from(status) {
+            Ok(ResponseStatus::OK) => Ok(data),
+            Ok(status) => Err(Error::from(status).into()),
+            Err(_) => bail!("Unknown status 0x{:02X} from reader", status),
+        }
+    }
+
+    /// The reader's firmware version and output power.
+    ///
+    /// The ru5102 crate's reader information doesn't make its fields public.
+    fn reader_information(&mut self) -> Result<(String, u8), failure::Error> {
+        // Version, type, protocols, frequency band, power and scan time
+        match self.command(CMD_READER_INFORMATION, &[])?[..] {
+            [major, minor, _, _, _, _, power, _] => Ok((format!("{}.{}", major, minor), power)),
+            ref data => bail!("Invalid reader information {:?}", data),
+        }
+    }
+}
+
+/// Password argument for a command, which isn't needed if it's zero.
+fn command_password(password: u32) -> Option<Vec<u8>> {
+    if password == 0 {
+        None
+    } else {
+        Some(password.to_be_bytes().to_vec())
+    }
+}
+
+fn tag_error(err: Error, password: u32) -> failure::Error {
+    match err {
+        Error::Protocol(ResponseStatus::AccessPasswordError) => {
+            AccessError::for_password(password).into()
+        }
+        err => err.into(),
+    }
+}
+
+fn memory_location(bank: MemoryBank) -> ::ru5102::MemoryLocation {
+    match bank {
+        MemoryBank::Reserved => ::ru5102::MemoryLocation::Password,
+        MemoryBank::EPC => ::ru5102::MemoryLocation::EPC,
+        MemoryBank::TID => ::ru5102::MemoryLocation::TID,
+        MemoryBank::User => ::ru5102::MemoryLocation::User,
+    }
+}
+
+impl Reader for RU5102 {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            max_power: Some(MAX_POWER),
+            ..Capabilities::default()
+        }
+    }
+
+    fn antennas(&mut self) -> Result<Vec<u8>, failure::Error> {
+        Ok(vec![0])
+    }
+
+    /// The firmware version. The reader doesn't report its temperature.
+    fn health(&mut self) -> Result<ReaderHealth, failure::Error> {
+        let (firmware, _) = self.reader_information()?;
+        Ok(ReaderHealth {
+            firmware: Some(firmware),
+            temperature: None,
+        })
+    }
+
+    /// The reader only accepts power, which is read back to show what was actually set.
+    fn configure(&mut self, settings: &ScanSettings) -> Result<ScanSettings, failure::Error> {
+        let power = match settings.power {
+            Some(power) => {
+                self.command(CMD_SET_POWER, &[power.min(MAX_POWER)])?;
+                Some(self.reader_information()?.1)
+            }
+            None => None,
+        };
+        Ok(ScanSettings {
+            detailed_scan: settings.detailed_scan,
+            power,
+            ..ScanSettings::default()
+        })
+    }
+
+    fn inventory(&mut self) -> Result<Vec<ScanResult>, failure::Error> {
+        Ok(self
+            .reader
+            .inventory()?
+            .into_iter()
+            .map(ScanResult::from_read)
+            .collect())
+    }
+
+    fn read_memory(
+        &mut self,
+        epc: &[u8],
+        bank: MemoryBank,
+        start: u8,
+        words: u8,
+        password: u32,
+    ) -> Result<Vec<u8>, failure::Error> {
+        let read_cmd = ::ru5102::ReadCommand {
+            epc: epc.to_owned(),
+            location: memory_location(bank),
+            start_address: start,
+            count: words,
+            password: command_password(password),
+            mask_address: None,
+            mask_length: None,
+        };
+
+        self.reader
+            .read_data(read_cmd)
+            .map_err(|err| tag_error(err, password))
+    }
+
+    fn write_memory(
+        &mut self,
+        epc: &[u8],
+        bank: MemoryBank,
+        start: u8,
+        data: &[u8],
+        password: u32,
+    ) -> Result<(), failure::Error> {
+        let write_cmd = ::ru5102::WriteCommand {
+            epc: epc.to_owned(),
+            location: memory_location(bank),
+            start_address: start,
+            data: data.to_owned(),
+            password: command_password(password),
+            mask_address: None,
+            mask_length: None,
+        };
+
+        self.reader
+            .write_data(write_cmd)
+            .map_err(|err| tag_error(err, password))
+    }
+
+    /// The tag is accessed with `password`, and must have a non-zero access password for the
+    /// lock to take effect.
+    fn lock(
+        &mut self,
+        epc: &[u8],
+        target: LockTarget,
+        action: LockAction,
+        password: u32,
+    ) -> Result<(), failure::Error> {
+        let select = match target {
+            LockTarget::KillPassword => 0x00,
+            LockTarget::AccessPassword => 0x01,
+            LockTarget::EPC => 0x02,
+            LockTarget::TID => 0x03,
+            LockTarget::User => 0x04,
+        };
+        // Writable (or readable, for passwords) always, permanently, when secured, or never
+        let protect = match action {
+            LockAction::Unlock => 0x00,
+            LockAction::PermaUnlock => 0x01,
+            LockAction::Lock => 0x02,
+            LockAction::PermaLock => 0x03,
+        };
+        let mut data = vec![(epc.len() / 2) as u8];
+        data.extend(epc);
+        data.extend(&[select, protect]);
+        data.extend(&password.to_be_bytes());
+
+        let port = self.handle.port()?;
+        port.write_all(&frame(ADDRESS, CMD_LOCK, &data))?;
+        let (status, _) = receive(port, CMD_LOCK)?;
+        match ResponseStatus::try_from(status) {
+            Ok(ResponseStatus::OK) => Ok(()),
+            Ok(status) => Err(tag_error(status.into(), password)),
+            Err(_) => bail!("Unknown status 0x{:02X} from reader", status),
+        }
+    }
+}
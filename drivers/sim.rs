@@ -0,0 +1,525 @@
+//! Simulated reader, for developing without hardware.
+//!
+//! The PORT argument is a comma-separated list of options, for example
+//! `tags=50,antennas=2,churn=5,seed=1`:
+//!
+//! * `tags` - size of the tag population (default 20)
+//! * `antennas` - number of antenna ports (default 4)
+//! * `churn` - percentage chance of each tag arriving or leaving on each inventory round (default 2)
+//! * `seed` - random seed, to make a session repeatable (default is random)
+//! * `password` - access password to give every tag, with the kill and access passwords locked
+//!   (default is no password)
+//! * `errors` - percentage chance of each inventory round failing, to test error handling
+//!   (default 0)
+use crate::rfid::{
+    parse_password, to_word, AccessError, Capabilities, LockAction, LockTarget, MemoryBank,
+    Reader, ReaderHealth, ScanResult, ScanSettings, SelectMask, Session, Target,
+};
+use failure::{bail, format_err};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::thread;
+use std::time;
+
+/// Time taken by a simulated inventory round, unless a scan round duration is set
+const INVENTORY_TIME: time::Duration = time::Duration::from_millis(100);
+
+/// Transmit power which tags' base RSSI is relative to, and the maximum power
+const DEFAULT_POWER: u8 = 30;
+const MAX_POWER: u8 = 33;
+/// How long a tag's session 1 inventoried flag stays at B before going back to A
+const S1_PERSISTENCE: time::Duration = time::Duration::from_secs(2);
+
+/// Chip models to simulate: (MDID, TMID, XTID, EPC bytes, user memory bytes)
+const CHIPS: &[(u16, u16, bool, usize, usize)] = &[
+    (0x1, 0x100, true, 16, 64),  // Impinj Monza 4D
+    (0x1, 0x160, true, 12, 0),   // Impinj Monza R6
+    (0x3, 0x412, false, 60, 64), // Alien Higgs-3
+    (0x6, 0x806, true, 16, 0),   // NXP UCODE G2iL
+    (0x6, 0x810, true, 16, 0),   // NXP UCODE 7
+    (0x6, 0x894, true, 16, 0),   // NXP UCODE 8
+];
+
+struct SimOptions {
+    tags: usize,
+    antennas: u8,
+    churn: f64,
+    seed: Option<u64>,
+    password: u32,
+    errors: f64,
+}
+
+impl SimOptions {
+    fn parse(spec: &str) -> Result<SimOptions, failure::Error> {
+        let mut options = SimOptions {
+            tags: 20,
+            antennas: 4,
+            churn: 0.02,
+            seed: None,
+            password: 0,
+            errors: 0.0,
+        };
+        for opt in spec.split(',').filter(|opt| !opt.is_empty()) {
+            let (key, value) = match opt.find('=') {
+                Some(i) => (&opt[..i], &opt[i + 1..]),
+                None => bail!("Invalid sim option '{}' (expected key=value)", opt),
+            };
+            match key {
+                "tags" => options.tags = value.parse()?,
+                "antennas" => options.antennas = value.parse()?,
+                "churn" => options.churn = value.parse::<f64>()? / 100.0,
+                "seed" => options.seed = Some(value.parse()?),
+                "password" => options.password = parse_password(value)?,
+                "errors" => options.errors = value.parse::<f64>()? / 100.0,
+                _ => bail!("Unknown sim option '{}'", key),
+            }
+        }
+        if options.antennas == 0 {
+            bail!("Simulated reader needs at least one antenna");
+        }
+        if !(0.0..=1.0).contains(&options.errors) {
+            bail!("Simulated error rate must be between 0 and 100");
+        }
+        Ok(options)
+    }
+}
+
+/// A simulated tag and the contents of its memory banks
+struct SimTag {
+    reserved: Vec<u8>,
+    /// The EPC bank: CRC, PC word, then the EPC itself.
+    epc_bank: Vec<u8>,
+    tid: Vec<u8>,
+    user: Vec<u8>,
+    /// Base RSSI on each antenna, or None if the tag isn't visible from it
+    rssi: Vec<Option<i8>>,
+    present: bool,
+    /// Lock state of each area of memory, if it's been changed from unlocked
+    locks: BTreeMap<LockTarget, LockAction>,
+    /// When the inventoried flag of each session was set to B, or None if it's A
+    inventoried: [Option<time::Instant>; 4],
+}
+
+/// Pack an SGTIN-96 with a fixed company prefix and item reference, and the given serial.
+fn sgtin96(serial: u64) -> Vec<u8> {
+    let filter: u128 = 1;
+    let partition: u128 = 5;
+    let company: u128 = 614_141;
+    let item: u128 = 812_345;
+    let value = (0x30 << 88)
+        | (filter << 85)
+        | (partition << 82)
+        | (company << 58)
+        | (item << 38)
+        | (u128::from(serial) & ((1 << 38) - 1));
+    value.to_be_bytes()[4..].to_vec()
+}
+
+impl SimTag {
+    fn random(rng: &mut StdRng, antennas: u8, password: u32) -> SimTag {
+        let (mdid, tmid, xtid, epc_bytes, user_bytes) = CHIPS[rng.gen_range(0, CHIPS.len())];
+
+        let mut tid = vec![
+            0xE2,
+            ((xtid as u8) << 7) | ((mdid >> 4) as u8 & 0x1F),
+            (((mdid & 0xF) as u8) << 4) | ((tmid >> 8) as u8 & 0xF),
+            tmid as u8,
+        ];
+        let serial: Vec<u8> = (0..6).map(|_| rng.gen()).collect();
+        if xtid {
+            // XTID header with a 48-bit serial and no optional segments
+            tid.extend(&[0x00, 0x01]);
+        }
+        tid.extend(&serial);
+
+        // A quarter of the tags still have a factory EPC
+        let epc = if rng.gen_bool(0.25) {
+            let mut epc = tid[0..4].to_vec();
+            epc.extend((0..8).map(|_| rng.gen::<u8>()));
+            epc
+        } else {
+            sgtin96(rng.gen())
+        };
+
+        let rssi = (0..antennas)
+            .map(|_| {
+                if rng.gen_bool(0.6) {
+                    Some(rng.gen_range(-75, -40))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let mut epc_bank = vec![0, 0, ((epc.len() / 2) as u8) << 3, 0];
+        if user_bytes > 0 {
+            // User memory indicator
+            epc_bank[2] |= 0x04;
+        }
+        epc_bank.extend(&epc);
+        epc_bank.resize(4 + epc_bytes, 0);
+
+        let mut reserved = vec![0; 4];
+        reserved.extend(&password.to_be_bytes());
+        let mut locks = BTreeMap::new();
+        if password != 0 {
+            locks.insert(LockTarget::KillPassword, LockAction::Lock);
+            locks.insert(LockTarget::AccessPassword, LockAction::Lock);
+        }
+
+        SimTag {
+            reserved,
+            epc_bank,
+            tid,
+            user: vec![0; user_bytes],
+            rssi,
+            present: rng.gen_bool(0.8),
+            locks,
+            inventoried: [None; 4],
+        }
+    }
+
+    /// The inventoried flag for a session, which is true for B. Flags go back to A when the
+    /// tag leaves the field, and in session 1 after `S1_PERSISTENCE` too.
+    fn flag(&mut self, session: Session) -> bool {
+        let flag = &mut self.inventoried[session as usize];
+        if !self.present
+            || session == Session::S1 && flag.is_some_and(|set| set.elapsed() > S1_PERSISTENCE)
+        {
+            *flag = None;
+        }
+        flag.is_some()
+    }
+
+    /// The EPC, with the length given in the PC word.
+    fn epc(&self) -> &[u8] {
+        let len = cmp::min((self.epc_bank[2] >> 3) as usize * 2, self.epc_bank.len() - 4);
+        &self.epc_bank[4..4 + len]
+    }
+
+    fn access_password(&self) -> u32 {
+        u32::from_be_bytes([
+            self.reserved[4],
+            self.reserved[5],
+            self.reserved[6],
+            self.reserved[7],
+        ])
+    }
+
+    /// Check the access password, returning whether the tag is in the secured state.
+    ///
+    /// Tags with a zero access password are always secured, so locks only have an effect once a
+    /// password has been set.
+    fn access(&self, password: u32) -> Result<bool, AccessError> {
+        if password == 0 {
+            Ok(self.access_password() == 0)
+        } else if password == self.access_password() {
+            Ok(true)
+        } else {
+            Err(AccessError::WrongPassword)
+        }
+    }
+
+    /// Check that memory can be accessed with the given password.
+    fn check_access(
+        &self,
+        bank: MemoryBank,
+        start: u8,
+        words: usize,
+        write: bool,
+        password: u32,
+    ) -> Result<(), failure::Error> {
+        let secured = self.access(password)?;
+        let first = LockTarget::for_memory(bank, start);
+        let last = LockTarget::for_memory(bank, (start as usize + words.saturating_sub(1)) as u8);
+        for target in [first, last].iter() {
+            // Locks on the banks only prevent writes, but locks on the passwords also prevent reads
+            let protected = write || bank == MemoryBank::Reserved;
+            match self.locks.get(target) {
+                Some(LockAction::PermaLock) if protected => bail!("{} is permalocked", target),
+                Some(LockAction::Lock) if protected && !secured => {
+                    return Err(AccessError::PasswordRequired.into())
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn memory(&self, bank: MemoryBank) -> &[u8] {
+        match bank {
+            MemoryBank::Reserved => &self.reserved,
+            MemoryBank::EPC => &self.epc_bank,
+            MemoryBank::TID => &self.tid,
+            MemoryBank::User => &self.user,
+        }
+    }
+
+    fn bank(&mut self, bank: MemoryBank) -> &mut Vec<u8> {
+        match bank {
+            MemoryBank::Reserved => &mut self.reserved,
+            MemoryBank::EPC => &mut self.epc_bank,
+            MemoryBank::TID => &mut self.tid,
+            MemoryBank::User => &mut self.user,
+        }
+    }
+}
+
+/// Byte range of a memory access, checking that it's within the bank.
+fn range(
+    data: &[u8],
+    bank: MemoryBank,
+    start: u8,
+    bytes: usize,
+) -> Result<(usize, usize), failure::Error> {
+    let start = start as usize * 2;
+    let end = start + bytes;
+    if end > data.len() {
+        bail!("Memory overrun accessing {:?} bank", bank);
+    }
+    Ok((start, end))
+}
+
+/// Simulated reader driver
+pub(crate) struct Sim {
+    rng: StdRng,
+    antennas: u8,
+    churn: f64,
+    errors: f64,
+    tags: Vec<SimTag>,
+    select: Option<SelectMask>,
+    /// Antennas to inventory on
+    enabled: Vec<u8>,
+    power: Option<u8>,
+    round_duration: Option<time::Duration>,
+    session: Option<Session>,
+    target: Option<Target>,
+    q: Option<u8>,
+    /// Flag inventoried in the last round, which is true for B
+    last_target: bool,
+}
+
+impl Sim {
+    pub fn new(spec: &str) -> Result<Sim, failure::Error> {
+        let options = SimOptions::parse(spec)?;
+        let mut rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let tags = (0..options.tags)
+            .map(|_| SimTag::random(&mut rng, options.antennas, options.password))
+            .collect();
+        Ok(Sim {
+            rng,
+            antennas: options.antennas,
+            churn: options.churn,
+            errors: options.errors,
+            tags,
+            select: None,
+            enabled: (0..options.antennas).collect(),
+            power: None,
+            round_duration: None,
+            session: None,
+            target: None,
+            q: None,
+            last_target: true,
+        })
+    }
+
+    fn find_tag(&mut self, epc: &[u8]) -> Result<&mut SimTag, failure::Error> {
+        self.tags
+            .iter_mut()
+            .find(|tag| tag.present && tag.epc() == epc)
+            .ok_or_else(|| format_err!("No response from tag {}", hex::encode_upper(epc)))
+    }
+}
+
+impl Reader for Sim {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            rssi: true,
+            antennas: true,
+            select: true,
+            session: true,
+            max_power: Some(MAX_POWER),
+        }
+    }
+
+    fn antennas(&mut self) -> Result<Vec<u8>, failure::Error> {
+        Ok((0..self.antennas).collect())
+    }
+
+    fn health(&mut self) -> Result<ReaderHealth, failure::Error> {
+        Ok(ReaderHealth {
+            firmware: Some("sim".to_string()),
+            temperature: Some(self.rng.gen_range(35, 40)),
+        })
+    }
+
+    /// Tags' inventoried flags are simulated for the session and target. With a Q, tags are
+    /// missed when they collide in one of its 2^Q slots, but otherwise the reader adapts Q to
+    /// read every tag. Without a session and target, every tag responds on every round.
+    fn configure(&mut self, settings: &ScanSettings) -> Result<ScanSettings, failure::Error> {
+        let enabled: Vec<u8> = match &settings.antennas {
+            Some(antennas) => antennas
+                .iter()
+                .cloned()
+                .filter(|antenna| *antenna < self.antennas)
+                .collect(),
+            None => (0..self.antennas).collect(),
+        };
+        if enabled.is_empty() {
+            bail!("At least one antenna must be enabled");
+        }
+        self.enabled = enabled;
+        self.power = settings.power.map(|power| cmp::min(power, MAX_POWER));
+        self.round_duration = settings.round_duration;
+        self.session = settings.session;
+        self.target = settings.target;
+        self.q = settings.q.map(|q| cmp::min(q, 15));
+        Ok(ScanSettings {
+            detailed_scan: settings.detailed_scan,
+            antennas: settings.antennas.as_ref().map(|_| self.enabled.to_owned()),
+            power: self.power,
+            session: self.session,
+            target: self.target,
+            q: self.q,
+            round_duration: self.round_duration,
+        })
+    }
+
+    fn inventory(&mut self) -> Result<Vec<ScanResult>, failure::Error> {
+        thread::sleep(self.round_duration.unwrap_or(INVENTORY_TIME));
+        if self.rng.gen_bool(self.errors) {
+            bail!("Simulated reader error");
+        }
+        let power_offset = self.power.unwrap_or(DEFAULT_POWER) as i8 - DEFAULT_POWER as i8;
+
+        // The flag to inventory, which is true for B, or None if every tag responds
+        let target = match (self.session, self.target) {
+            (Some(_), Some(Target::A)) => Some(false),
+            (Some(_), Some(Target::B)) => Some(true),
+            (Some(_), Some(Target::AB)) => Some(!self.last_target),
+            _ => None,
+        };
+        if let Some(target) = target {
+            self.last_target = target;
+        }
+
+        let mut result = Vec::new();
+        for tag in self.tags.iter_mut() {
+            if self.rng.gen_bool(self.churn) {
+                tag.present = !tag.present;
+            }
+        }
+        for antenna in self.enabled.clone() {
+            let mut responding = Vec::new();
+            for (i, tag) in self.tags.iter_mut().enumerate() {
+                let selected = match &self.select {
+                    Some(mask) => mask.matches(tag.memory(mask.bank)),
+                    None => true,
+                };
+                let flag = match (self.session, target) {
+                    (Some(session), Some(target)) => tag.flag(session) == target,
+                    _ => true,
+                };
+                let base_rssi = match tag.rssi[antenna as usize] {
+                    Some(rssi) if selected && flag && tag.present => rssi + power_offset,
+                    _ => continue,
+                };
+                // Weaker tags are missed more often
+                if self.rng.gen_range(-80, -30) > base_rssi + 25 {
+                    continue;
+                }
+                responding.push((i, base_rssi));
+            }
+            // Each tag is read if no other tag picks the same slot
+            let slots = self.q.map(|q| f64::from(1u32 << q));
+            let unique = slots.map_or(1.0, |slots| {
+                (1.0 - 1.0 / slots).powi(responding.len() as i32 - 1)
+            });
+            for (i, base_rssi) in responding {
+                if !self.rng.gen_bool(unique) {
+                    continue;
+                }
+                let tag = &mut self.tags[i];
+                if let (Some(session), Some(target)) = (self.session, target) {
+                    // Reading a tag flips its flag, so it doesn't respond to the target again
+                    tag.inventoried[session as usize] = if target {
+                        None
+                    } else {
+                        Some(time::Instant::now())
+                    };
+                }
+                let mut res = ScanResult::from_read(tag.epc().to_vec());
+                res.pc = to_word(&tag.epc_bank[2..4]);
+                res.rssi = Some(base_rssi + self.rng.gen_range(-3, 4));
+                res.antenna = Some(antenna);
+                result.push(res);
+            }
+        }
+        Ok(result)
+    }
+
+    fn read_memory(
+        &mut self,
+        epc: &[u8],
+        bank: MemoryBank,
+        start: u8,
+        words: u8,
+        password: u32,
+    ) -> Result<Vec<u8>, failure::Error> {
+        let tag = self.find_tag(epc)?;
+        tag.check_access(bank, start, words as usize, false, password)?;
+        let data = tag.bank(bank);
+        let (start, end) = range(data, bank, start, words as usize * 2)?;
+        Ok(data[start..end].to_vec())
+    }
+
+    fn write_memory(
+        &mut self,
+        epc: &[u8],
+        bank: MemoryBank,
+        start: u8,
+        data: &[u8],
+        password: u32,
+    ) -> Result<(), failure::Error> {
+        if bank == MemoryBank::TID {
+            bail!("TID bank is read-only");
+        }
+        let tag = self.find_tag(epc)?;
+        tag.check_access(bank, start, data.len() / 2, true, password)?;
+        let memory = tag.bank(bank);
+        let (start, end) = range(memory, bank, start, data.len())?;
+        memory[start..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn select(&mut self, mask: Option<&SelectMask>) -> Result<(), failure::Error> {
+        self.select = mask.cloned();
+        Ok(())
+    }
+
+    fn lock(
+        &mut self,
+        epc: &[u8],
+        target: LockTarget,
+        action: LockAction,
+        password: u32,
+    ) -> Result<(), failure::Error> {
+        let tag = self.find_tag(epc)?;
+        if !tag.access(password)? {
+            return Err(AccessError::PasswordRequired.into());
+        }
+        match tag.locks.get(&target) {
+            Some(current) if current.is_permanent() && *current != action => {
+                bail!("{} is {}", target, current)
+            }
+            _ => {}
+        }
+        tag.locks.insert(target, action);
+        Ok(())
+    }
+}
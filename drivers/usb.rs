@@ -0,0 +1,133 @@
+//! Finding USB serial adapters by vendor, product and serial number, so a reader can be found
+//! again if its device name changes when it's plugged back in.
+//!
+//! This reads from sysfs, so it only finds devices on Linux.
+use failure::{bail, format_err};
+use std::fmt;
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+
+#[cfg(target_os = "linux")]
+const SYS_TTY: &str = "/sys/class/tty";
+/// Levels of sysfs to look up from a tty for the USB device it belongs to
+#[cfg(target_os = "linux")]
+const MAX_DEPTH: usize = 4;
+
+/// A USB device, identified by its vendor ID, product ID and optionally its serial number
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct UsbDevice {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial: Option<String>,
+}
+
+impl fmt::Display for UsbDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "usb:{:04x}:{:04x}", self.vid, self.pid)?;
+        if let Some(serial) = &self.serial {
+            write!(f, ":{}", serial)?;
+        }
+        Ok(())
+    }
+}
+
+impl UsbDevice {
+    /// Parse a device given as `usb:VID:PID[:SERIAL]`, with the IDs in hex.
+    pub fn parse(spec: &str) -> Result<UsbDevice, failure::Error> {
+        let mut parts = match spec.strip_prefix("usb:") {
+            Some(rest) => rest.splitn(3, ':'),
+            None => bail!("Not a USB device: '{}'", spec),
+        };
+        let mut id = |name| {
+            let value = parts.next().unwrap_or("");
+            u16::from_str_radix(value, 16).map_err(|_| {
+                format_err!(
+                    "Invalid USB {} '{}' (expected usb:VID:PID[:SERIAL])",
+                    name,
+                    value
+                )
+            })
+        };
+        let vid = id("vendor ID")?;
+        let pid = id("product ID")?;
+        Ok(UsbDevice {
+            vid,
+            pid,
+            serial: parts.next().map(|serial| serial.to_string()),
+        })
+    }
+
+    /// Whether `other` is this device, ignoring the serial number if this doesn't have one.
+    #[cfg(target_os = "linux")]
+    fn matches(&self, other: &UsbDevice) -> bool {
+        self.vid == other.vid
+            && self.pid == other.pid
+            && (self.serial.is_none() || self.serial == other.serial)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_attribute(dir: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(dir.join(name))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+/// The USB device which the tty with this name belongs to, if it's a USB serial adapter.
+#[cfg(target_os = "linux")]
+fn tty_device(name: &str) -> Option<UsbDevice> {
+    let mut dir: PathBuf = fs::canonicalize(Path::new(SYS_TTY).join(name).join("device")).ok()?;
+    for _ in 0..MAX_DEPTH {
+        if let Some(vid) = read_attribute(&dir, "idVendor") {
+            return Some(UsbDevice {
+                vid: u16::from_str_radix(&vid, 16).ok()?,
+                pid: u16::from_str_radix(&read_attribute(&dir, "idProduct")?, 16).ok()?,
+                serial: read_attribute(&dir, "serial"),
+            });
+        }
+        dir = dir.parent()?.to_path_buf();
+    }
+    None
+}
+
+/// The USB device a serial port belongs to, if it's a USB serial adapter.
+#[cfg(target_os = "linux")]
+pub(crate) fn identify(port: &str) -> Option<UsbDevice> {
+    let path = fs::canonicalize(port).ok()?;
+    tty_device(path.file_name()?.to_str()?)
+}
+
+/// Serial ports can't be identified without sysfs.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn identify(_port: &str) -> Option<UsbDevice> {
+    None
+}
+
+/// Find the serial port of a USB serial adapter.
+#[cfg(target_os = "linux")]
+pub(crate) fn find(device: &UsbDevice) -> Result<String, failure::Error> {
+    let mut names: Vec<String> = match fs::read_dir(SYS_TTY) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect(),
+        Err(_) => bail!("Can't look for USB devices, {} isn't available", SYS_TTY),
+    };
+    names.sort();
+    for name in names {
+        if tty_device(&name).is_some_and(|found| device.matches(&found)) {
+            return Ok(format!("/dev/{}", name));
+        }
+    }
+    bail!("No serial port found for USB device {}", device)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn find(device: &UsbDevice) -> Result<String, failure::Error> {
+    bail!(
+        "Can't look for USB device {}, this is only supported on Linux",
+        device
+    )
+}
@@ -0,0 +1,349 @@
+//! Encoding of EPCs for writing to tags, and of Select masks for filtering inventory.
+//!
+//! The gs1 crate only decodes EPCs, so this implements binary encoding for the common 96-bit
+//! schemes from their EPC tag URIs.
+//!
+//! # Reference
+//! GS1 EPC TDS Section 14
+use crate::rfid::{MemoryBank, SelectMask, EPC_START_BIT};
+use failure::{bail, format_err};
+
+/// A row of a partition table: (company prefix bits, company prefix digits, reference bits,
+/// reference digits)
+type Partition = (u32, usize, u32, usize);
+
+/// Partition tables, indexed by partition value.
+///
+/// TDS Tables 14-2 (SGTIN), 14-5 (SSCC) and 14-11 (GRAI)
+const SGTIN_PARTITIONS: [Partition; 7] = [
+    (40, 12, 4, 1),
+    (37, 11, 7, 2),
+    (34, 10, 10, 3),
+    (30, 9, 14, 4),
+    (27, 8, 17, 5),
+    (24, 7, 20, 6),
+    (20, 6, 24, 7),
+];
+
+const SSCC_PARTITIONS: [Partition; 7] = [
+    (40, 12, 18, 5),
+    (37, 11, 21, 6),
+    (34, 10, 24, 7),
+    (30, 9, 28, 8),
+    (27, 8, 31, 9),
+    (24, 7, 34, 10),
+    (20, 6, 38, 11),
+];
+
+const GRAI_PARTITIONS: [Partition; 7] = [
+    (40, 12, 4, 0),
+    (37, 11, 7, 1),
+    (34, 10, 10, 2),
+    (30, 9, 14, 3),
+    (27, 8, 17, 4),
+    (24, 7, 20, 5),
+    (20, 6, 24, 6),
+];
+
+/// Accumulates a 96-bit EPC, most significant field first.
+struct BitWriter {
+    value: u128,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { value: 0, bits: 0 }
+    }
+
+    fn write(&mut self, value: u64, bits: u32) -> Result<(), failure::Error> {
+        if bits < 64 && value >> bits != 0 {
+            bail!("Value {} doesn't fit in {} bits", value, bits);
+        }
+        self.value = (self.value << bits) | u128::from(value);
+        self.bits += bits;
+        Ok(())
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        assert_eq!(self.bits, 96);
+        self.value.to_be_bytes()[4..].to_vec()
+    }
+
+    /// The bits written so far as a Select mask starting at `pointer` in the EPC bank.
+    fn into_mask(self, pointer: u16) -> SelectMask {
+        let bytes = self.bits.div_ceil(8) as usize;
+        let value = self.value << (bytes as u32 * 8 - self.bits);
+        SelectMask {
+            bank: MemoryBank::EPC,
+            pointer,
+            mask: value.to_be_bytes()[16 - bytes..].to_vec(),
+            length: self.bits as u16,
+        }
+    }
+}
+
+/// Parse a decimal field from a URI, which must have exactly `digits` digits.
+fn parse_digits(field: &str, digits: usize, name: &str) -> Result<u64, failure::Error> {
+    if field.len() != digits || !field.chars().all(|c| c.is_ascii_digit()) {
+        bail!("{} must be {} digits, got '{}'", name, digits, field);
+    }
+    if digits == 0 {
+        return Ok(0);
+    }
+    Ok(field.parse()?)
+}
+
+/// Parse a serial number field, which must not have leading zeros.
+fn parse_serial(field: &str) -> Result<u64, failure::Error> {
+    if field.is_empty()
+        || !field.chars().all(|c| c.is_ascii_digit())
+        || (field.len() > 1 && field.starts_with('0'))
+    {
+        bail!("Invalid serial number '{}'", field);
+    }
+    Ok(field.parse()?)
+}
+
+fn parse_filter(field: &str) -> Result<u64, failure::Error> {
+    match field.parse::<u64>() {
+        Ok(filter) if filter < 8 => Ok(filter),
+        _ => bail!("Invalid filter value '{}'", field),
+    }
+}
+
+/// Find the partition for a company prefix of the given length.
+fn partition(
+    table: &[Partition; 7],
+    company_digits: usize,
+) -> Result<(u64, Partition), failure::Error> {
+    table
+        .iter()
+        .enumerate()
+        .find(|(_, p)| p.1 == company_digits)
+        .map(|(i, p)| (i as u64, *p))
+        .ok_or_else(|| format_err!("Company prefix must be 6 to 12 digits"))
+}
+
+fn fields<'a>(value: &'a str, count: usize, scheme: &str) -> Result<Vec<&'a str>, failure::Error> {
+    let fields: Vec<&str> = value.split('.').collect();
+    if fields.len() != count {
+        bail!("{} tag URI should have {} fields", scheme, count);
+    }
+    Ok(fields)
+}
+
+/// Encode filter, company prefix, reference and serial for SGTIN-96 or GRAI-96.
+fn encode_with_serial(
+    header: u64,
+    table: &[Partition; 7],
+    value: &str,
+    scheme: &str,
+) -> Result<Vec<u8>, failure::Error> {
+    let f = fields(value, 4, scheme)?;
+    let (partition, (company_bits, company_digits, ref_bits, ref_digits)) =
+        partition(table, f[1].len())?;
+    let mut writer = BitWriter::new();
+    writer.write(header, 8)?;
+    writer.write(parse_filter(f[0])?, 3)?;
+    writer.write(partition, 3)?;
+    writer.write(parse_digits(f[1], company_digits, "Company prefix")?, company_bits)?;
+    writer.write(parse_digits(f[2], ref_digits, "Reference")?, ref_bits)?;
+    writer.write(parse_serial(f[3])?, 38)?;
+    Ok(writer.into_bytes())
+}
+
+fn encode_sscc96(value: &str) -> Result<Vec<u8>, failure::Error> {
+    let f = fields(value, 3, "SSCC-96")?;
+    let (partition, (company_bits, company_digits, ref_bits, ref_digits)) =
+        partition(&SSCC_PARTITIONS, f[1].len())?;
+    let mut writer = BitWriter::new();
+    writer.write(0x31, 8)?;
+    writer.write(parse_filter(f[0])?, 3)?;
+    writer.write(partition, 3)?;
+    writer.write(parse_digits(f[1], company_digits, "Company prefix")?, company_bits)?;
+    writer.write(parse_digits(f[2], ref_digits, "Serial reference")?, ref_bits)?;
+    writer.write(0, 24)?;
+    Ok(writer.into_bytes())
+}
+
+fn encode_gid96(value: &str) -> Result<Vec<u8>, failure::Error> {
+    let f = fields(value, 3, "GID-96")?;
+    let mut writer = BitWriter::new();
+    writer.write(0x35, 8)?;
+    writer.write(parse_serial(f[0])?, 28)?;
+    writer.write(parse_serial(f[1])?, 24)?;
+    writer.write(parse_serial(f[2])?, 36)?;
+    Ok(writer.into_bytes())
+}
+
+/// Encode an EPC tag URI, such as `urn:epc:tag:sgtin-96:3.0614141.812345.6789`.
+pub(crate) fn encode_tag_uri(uri: &str) -> Result<Vec<u8>, failure::Error> {
+    let rest = match uri.strip_prefix("urn:epc:tag:") {
+        Some(rest) => rest,
+        None => bail!("Not an EPC tag URI: '{}'", uri),
+    };
+    let (scheme, value) = match rest.find(':') {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None => bail!("Not an EPC tag URI: '{}'", uri),
+    };
+    match scheme {
+        "sgtin-96" => encode_with_serial(0x30, &SGTIN_PARTITIONS, value, "SGTIN-96"),
+        "sscc-96" => encode_sscc96(value),
+        "grai-96" => encode_with_serial(0x33, &GRAI_PARTITIONS, value, "GRAI-96"),
+        "gid-96" => encode_gid96(value),
+        _ => bail!("Unsupported EPC scheme '{}'", scheme),
+    }
+}
+
+/// Header value and partition table of the schemes which can be selected by filter value and
+/// company prefix.
+fn scheme_partitions(scheme: &str) -> Result<(u64, &'static [Partition; 7]), failure::Error> {
+    match scheme {
+        "sgtin-96" => Ok((0x30, &SGTIN_PARTITIONS)),
+        "sscc-96" => Ok((0x31, &SSCC_PARTITIONS)),
+        "grai-96" => Ok((0x33, &GRAI_PARTITIONS)),
+        _ => bail!("Can't select on scheme '{}'", scheme),
+    }
+}
+
+/// Parse a bit pointer in decimal or, with a `0x` prefix, hex.
+fn parse_pointer(value: &str) -> Result<u16, failure::Error> {
+    let pointer = match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    pointer.map_err(|_| format_err!("Invalid bit pointer '{}'", value))
+}
+
+/// Parse a hex mask, which may have an odd number of digits.
+fn parse_hex_mask(
+    bank: MemoryBank,
+    value: &str,
+    pointer: u16,
+) -> Result<SelectMask, failure::Error> {
+    if value.is_empty() || value.len() > 64 {
+        bail!("Mask must be between 1 and 64 hex digits");
+    }
+    let mut digits = value.to_string();
+    if digits.len() % 2 == 1 {
+        digits.push('0');
+    }
+    Ok(SelectMask {
+        bank,
+        pointer,
+        mask: hex::decode(&digits).map_err(|e| format_err!("Invalid hex mask: {}", e))?,
+        length: value.len() as u16 * 4,
+    })
+}
+
+/// Parse a Select mask entered by the user, which can be:
+///
+/// * a hex EPC prefix, such as `E280`
+/// * a bank, hex mask and optional bit pointer, such as `tid:E2801100` or `epc:3074@0x20`
+/// * a GS1 company prefix, such as `gs1:0614141`
+/// * a filter value and company prefix in the form of an EPC tag URI, such as
+///   `urn:epc:tag:sgtin-96:3.0614141`
+///
+/// An empty string clears the mask.
+pub(crate) fn parse_select(input: &str) -> Result<Option<SelectMask>, failure::Error> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+    let input = input.strip_prefix("urn:epc:tag:").unwrap_or(input);
+    let (kind, value) = match input.find(':') {
+        Some(i) => (&input[..i], &input[i + 1..]),
+        None => ("epc", input),
+    };
+    let (value, pointer) = match value.find('@') {
+        Some(i) => (&value[..i], Some(parse_pointer(&value[i + 1..])?)),
+        None => (value, None),
+    };
+    let mask = match kind {
+        "epc" => parse_hex_mask(MemoryBank::EPC, value, pointer.unwrap_or(EPC_START_BIT))?,
+        "tid" => parse_hex_mask(MemoryBank::TID, value, pointer.unwrap_or(0))?,
+        "user" => parse_hex_mask(MemoryBank::User, value, pointer.unwrap_or(0))?,
+        "gs1" => {
+            // The partition and company prefix, which follow the 8 bit header and 3 bit filter
+            let (partition, (company_bits, company_digits, _, _)) =
+                partition(&SGTIN_PARTITIONS, value.len())?;
+            let mut writer = BitWriter::new();
+            writer.write(partition, 3)?;
+            writer.write(parse_digits(value, company_digits, "Company prefix")?, company_bits)?;
+            writer.into_mask(EPC_START_BIT + 11)
+        }
+        scheme => {
+            let (header, table) = scheme_partitions(scheme)?;
+            let f = fields(value, 2, scheme)?;
+            let (partition, (company_bits, company_digits, _, _)) = partition(table, f[1].len())?;
+            let mut writer = BitWriter::new();
+            writer.write(header, 8)?;
+            writer.write(parse_filter(f[0])?, 3)?;
+            writer.write(partition, 3)?;
+            writer.write(parse_digits(f[1], company_digits, "Company prefix")?, company_bits)?;
+            writer.into_mask(EPC_START_BIT)
+        }
+    };
+    Ok(Some(mask))
+}
+
+/// Parse an EPC entered by the user, either as hex or as an EPC tag URI.
+pub(crate) fn parse_epc(input: &str) -> Result<Vec<u8>, failure::Error> {
+    let input = input.trim();
+    if input.starts_with("urn:") {
+        return encode_tag_uri(input);
+    }
+    let hex: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+    let epc = hex::decode(&hex).map_err(|e| format_err!("Invalid hex EPC: {}", e))?;
+    if epc.is_empty() || !epc.len().is_multiple_of(2) {
+        bail!("EPC must be a whole number of 16-bit words");
+    }
+    if epc.len() > 62 {
+        bail!("EPC can be at most 31 words long");
+    }
+    Ok(epc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(uri: &str) -> String {
+        hex::encode_upper(encode_tag_uri(uri).unwrap())
+    }
+
+    #[test]
+    fn encodes_tag_uris() {
+        assert_eq!(
+            encode("urn:epc:tag:sgtin-96:3.0614141.812345.6789"),
+            "3074257BF7194E4000001A85"
+        );
+        assert_eq!(
+            encode("urn:epc:tag:sscc-96:3.0614141.1234567890"),
+            "3174257BF4499602D2000000"
+        );
+        assert_eq!(
+            encode("urn:epc:tag:grai-96:3.0614141.12345.400"),
+            "3374257BF40C0E4000000190"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_tag_uris() {
+        assert!(encode_tag_uri("urn:epc:id:sgtin:0614141.812345.6789").is_err());
+        assert!(encode_tag_uri("urn:epc:tag:sgtin-198:3.0614141.812345.6789").is_err());
+        assert!(encode_tag_uri("urn:epc:tag:sgtin-96:8.0614141.812345.6789").is_err());
+        assert!(encode_tag_uri("urn:epc:tag:sgtin-96:3.0614141.812345").is_err());
+    }
+
+    #[test]
+    fn parses_epcs() {
+        assert_eq!(
+            parse_epc("3074 257B F719 4E40 0000 1A85").unwrap(),
+            parse_epc("urn:epc:tag:sgtin-96:3.0614141.812345.6789").unwrap()
+        );
+        assert!(parse_epc("3074257").is_err());
+        assert!(parse_epc("307425").is_err());
+    }
+}
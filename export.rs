@@ -0,0 +1,215 @@
+//! Headless mode: scan for a fixed time and write the resulting tag list as CSV or JSON.
+use crate::app::{App, TagStats};
+use crate::audit::{self, Audit, ExpectedTag};
+use crate::rfid::{ReaderEvent, ScanResult};
+use crate::tagdb::TagDb;
+use chrono::{DateTime, Local};
+use failure::{bail, format_err};
+use gs1::epc::tid::XTIDHeader;
+use gs1::epc;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+/// How often to collect results from the scan thread
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(250);
+
+pub(crate) const FORMATS: &[&str] = &["json", "csv"];
+
+#[derive(Serialize)]
+struct ExportRow {
+    epc: String,
+    uri: Option<String>,
+    manufacturer: Option<String>,
+    model: Option<String>,
+    xtid_header: Option<String>,
+    serial: Option<String>,
+    best_rssi: Option<i8>,
+    antennas: Vec<u8>,
+    /// The readers which saw the tag, as PORT:DRIVER
+    readers: Vec<String>,
+    reads: u64,
+    first_seen: String,
+    last_seen: String,
+}
+
+/// Re-encode the XTID header as the word it was read from.
+fn xtid_word(xtid: &XTIDHeader) -> u16 {
+    let serial = if xtid.serial_size == 0 {
+        0
+    } else {
+        (xtid.serial_size - 48) / 16 + 1
+    };
+    (xtid.extended_header as u16) << 15
+        | (xtid.user_memory_permalock as u16) << 5
+        | (xtid.blockwrite_blockerase as u16) << 4
+        | (xtid.optional_command_support as u16) << 3
+        | serial
+}
+
+/// Convert an `Instant` to local wall-clock time.
+pub(crate) fn wall_clock(instant: time::Instant) -> DateTime<Local> {
+    let now = time::SystemTime::now();
+    DateTime::from(now - instant.elapsed())
+}
+
+impl ExportRow {
+    fn new(item: &ScanResult, stats: &TagStats, readers: &[String]) -> ExportRow {
+        ExportRow {
+            epc: hex::encode_upper(&item.epc),
+            uri: epc::decode_binary(&item.epc).ok().map(|val| val.to_uri()),
+            manufacturer: item.manufacturer(),
+            model: item.model(),
+            xtid_header: item.xtid_header.map(|xtid| format!("{:04X}", xtid_word(&xtid))),
+            serial: item.serial.as_ref().map(hex::encode_upper),
+            best_rssi: stats.best_rssi,
+            antennas: stats.antennas.iter().cloned().collect(),
+            readers: stats
+                .readers
+                .iter()
+                .map(|reader| readers[*reader].to_owned())
+                .collect(),
+            reads: stats.reads,
+            first_seen: wall_clock(stats.first_seen).to_rfc3339(),
+            last_seen: wall_clock(item.last_seen).to_rfc3339(),
+        }
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        let antennas: Vec<String> = self.antennas.iter().map(|a| a.to_string()).collect();
+        vec![
+            self.epc.to_owned(),
+            self.uri.to_owned().unwrap_or_default(),
+            self.manufacturer.to_owned().unwrap_or_default(),
+            self.model.to_owned().unwrap_or_default(),
+            self.xtid_header.to_owned().unwrap_or_default(),
+            self.serial.to_owned().unwrap_or_default(),
+            self.best_rssi.map(|rssi| rssi.to_string()).unwrap_or_default(),
+            antennas.join(" "),
+            self.readers.join(" "),
+            self.reads.to_string(),
+            self.first_seen.to_owned(),
+            self.last_seen.to_owned(),
+        ]
+    }
+}
+
+const CSV_HEADER: &[&str] = &[
+    "epc",
+    "uri",
+    "manufacturer",
+    "model",
+    "xtid_header",
+    "serial",
+    "best_rssi",
+    "antennas",
+    "readers",
+    "reads",
+    "first_seen",
+    "last_seen",
+];
+
+fn write_rows(rows: &[ExportRow], format: &str, out: &mut dyn Write) -> Result<(), failure::Error> {
+    match format {
+        "json" => {
+            serde_json::to_writer_pretty(&mut *out, rows)?;
+            writeln!(out)?;
+        }
+        "csv" => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(CSV_HEADER)?;
+            for row in rows {
+                writer.write_record(row.csv_record())?;
+            }
+            writer.flush()?;
+        }
+        _ => bail!("Invalid output format (shouldn't happen)"),
+    }
+    Ok(())
+}
+
+/// Parse a duration such as `30`, `30s`, `5m` or `1h`.
+pub(crate) fn parse_duration(value: &str) -> Result<time::Duration, failure::Error> {
+    let (number, multiplier) = match value.chars().last() {
+        Some('s') => (&value[..value.len() - 1], 1),
+        Some('m') => (&value[..value.len() - 1], 60),
+        Some('h') => (&value[..value.len() - 1], 3600),
+        _ => (value, 1),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format_err!("Invalid duration '{}'", value))?;
+    let secs = number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format_err!("Invalid duration '{}'", value))?;
+    Ok(time::Duration::from_secs(secs))
+}
+
+/// Collect scan results for `duration`, then write all tags seen to `path` (or stdout).
+///
+/// If there are expected tags, the audit of them is written instead.
+pub(crate) fn run(
+    scan_rx: &mpsc::Receiver<ReaderEvent>,
+    readers: Vec<String>,
+    format: &str,
+    duration: time::Duration,
+    path: Option<&str>,
+    db: Option<TagDb>,
+    expected: Vec<ExpectedTag>,
+) -> Result<(), failure::Error> {
+    let mut app = App::new(readers);
+    app.show_inactive = true;
+    app.db = db;
+    app.expected = expected;
+
+    let start = time::Instant::now();
+    while start.elapsed() < duration {
+        thread::sleep(POLL_INTERVAL);
+        app.update_items(scan_rx);
+        if let Some(reader) = app.status.iter().position(|status| status.stopped) {
+            bail!("Reader {} stopped", app.readers[reader]);
+        }
+    }
+    if let Some(db) = &mut app.db {
+        db.flush()?;
+    }
+
+    let mut out: Box<dyn Write> = match path {
+        Some(path) => Box::new(
+            File::create(path).map_err(|e| format_err!("Unable to create {}: {}", path, e))?,
+        ),
+        None => Box::new(io::stdout()),
+    };
+    if !app.expected.is_empty() {
+        return audit::write(&Audit::new(&app), format, &mut out);
+    }
+
+    let rows: Vec<ExportRow> = app
+        .get_items()
+        .into_iter()
+        .map(|item| ExportRow::new(item, &app.stats[&item.epc], &app.readers))
+        .collect();
+    write_rows(&rows, format, &mut out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        let secs = |value| parse_duration(value).unwrap().as_secs();
+        assert_eq!(secs("30"), 30);
+        assert_eq!(secs("30s"), 30);
+        assert_eq!(secs("5m"), 300);
+        assert_eq!(secs("1h"), 3600);
+        assert_eq!(secs("0"), 0);
+        assert_eq!(secs("18446744073709551615"), u64::MAX);
+        for invalid in &["", "s", "5d", "-1", "1.5m", "m5", "18446744073709551615h"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
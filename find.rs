@@ -0,0 +1,211 @@
+//! "Find tag" mode, which shows how strongly a single tag is being read so it can be located.
+use crate::block;
+use crate::rfid::ScanResult;
+use gs1::epc;
+use std::collections::VecDeque;
+use std::time;
+use tui::buffer::Buffer;
+use tui::layout::{Constraint, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Block, Borders, Gauge, Paragraph, Text, Widget};
+
+/// Reads in this period are used to work out the read rate
+const RATE_WINDOW: time::Duration = time::Duration::from_secs(1);
+/// The strongest RSSI in this period is shown as the signal
+const SIGNAL_WINDOW: time::Duration = time::Duration::from_millis(500);
+/// RSSI shown as an empty signal bar
+const RSSI_FLOOR: f64 = -90.0;
+/// RSSI shown as a full signal bar
+const RSSI_CEILING: f64 = -30.0;
+/// Read rate shown as a full bar
+const MAX_RATE: f64 = 50.0;
+/// Time between bells for the weakest and strongest signal
+const SLOWEST_BELL: time::Duration = time::Duration::from_millis(1000);
+const FASTEST_BELL: time::Duration = time::Duration::from_millis(100);
+
+/// A read of the tag being found
+#[derive(Clone)]
+struct Read {
+    time: time::Instant,
+    rssi: Option<i8>,
+    antenna: Option<u8>,
+}
+
+pub(crate) struct FindState {
+    pub epc: Vec<u8>,
+    /// Reads in the last `RATE_WINDOW`, oldest first
+    reads: VecDeque<Read>,
+    last_read: Option<Read>,
+    /// Whether to ring the terminal bell as the tag is read
+    pub bell: bool,
+    last_bell: time::Instant,
+}
+
+impl FindState {
+    pub fn new(epc: &[u8]) -> FindState {
+        FindState {
+            epc: epc.to_owned(),
+            reads: VecDeque::new(),
+            last_read: None,
+            bell: false,
+            last_bell: time::Instant::now(),
+        }
+    }
+
+    pub fn update(&mut self, result: &ScanResult) {
+        if result.epc != self.epc || !result.read {
+            return;
+        }
+        let read = Read {
+            time: result.last_seen,
+            rssi: result.rssi,
+            antenna: result.antenna,
+        };
+        self.last_read = Some(read.clone());
+        self.reads.push_back(read);
+    }
+
+    fn prune(&mut self) {
+        while self
+            .reads
+            .front()
+            .is_some_and(|read| read.time.elapsed() > RATE_WINDOW)
+        {
+            self.reads.pop_front();
+        }
+    }
+
+    /// Reads per second
+    fn rate(&self) -> f64 {
+        self.reads.len() as f64 / RATE_WINDOW.as_secs_f64()
+    }
+
+    /// The strongest recent RSSI
+    fn rssi(&self) -> Option<i8> {
+        self.reads
+            .iter()
+            .filter(|read| read.time.elapsed() < SIGNAL_WINDOW)
+            .filter_map(|read| read.rssi)
+            .max()
+    }
+
+    /// How strongly the tag is being read, between 0 and 1, or None if it isn't.
+    ///
+    /// This is from the RSSI if the reader reports it, otherwise the read rate.
+    fn strength(&self) -> Option<f64> {
+        if self.reads.is_empty() {
+            return None;
+        }
+        let strength = match self.rssi() {
+            Some(rssi) => (f64::from(rssi) - RSSI_FLOOR) / (RSSI_CEILING - RSSI_FLOOR),
+            None => self.rate() / MAX_RATE,
+        };
+        Some(strength.clamp(0.0, 1.0))
+    }
+
+    /// Forget old reads, returning true if it's time to ring the bell.
+    pub fn poll(&mut self) -> bool {
+        self.prune();
+        let strength = match (self.bell, self.strength()) {
+            (true, Some(strength)) => strength,
+            _ => return false,
+        };
+        let interval = SLOWEST_BELL.as_secs_f64()
+            - strength * (SLOWEST_BELL - FASTEST_BELL).as_secs_f64();
+        if self.last_bell.elapsed().as_secs_f64() >= interval {
+            self.last_bell = time::Instant::now();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub(crate) struct FindView<'a> {
+    pub state: &'a FindState,
+}
+
+impl<'a> FindView<'a> {
+    pub fn new(state: &'a FindState) -> FindView<'a> {
+        FindView { state }
+    }
+}
+
+fn gauge_style(strength: f64) -> Style {
+    Style::default().fg(if strength > 0.66 {
+        Color::Green
+    } else if strength > 0.33 {
+        Color::Yellow
+    } else {
+        Color::Red
+    })
+}
+
+impl<'a> Widget for FindView<'a> {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        let state = self.state;
+        let mut outer = block("Find Tag");
+        outer.draw(area, buf);
+        let rects = Layout::default()
+            .constraints(
+                [
+                    Constraint::Length(2),
+                    Constraint::Length(7),
+                    Constraint::Length(5),
+                    Constraint::Min(0),
+                ]
+                .as_ref(),
+            )
+            .split(outer.inner(area));
+
+        let mut header = hex::encode_upper(&state.epc);
+        if let Ok(val) = epc::decode_binary(&state.epc) {
+            header.push_str(&format!(" ({})", val.to_uri()));
+        }
+        Paragraph::new([Text::styled(header, Style::default().modifier(Modifier::BOLD))].iter())
+            .draw(rects[0], buf);
+
+        let strength = state.strength().unwrap_or(0.0);
+        let signal = match (state.rssi(), state.reads.is_empty()) {
+            (_, true) => "Not seen".to_string(),
+            (Some(rssi), false) => format!("{} dBm", rssi),
+            (None, false) => "Seen".to_string(),
+        };
+        Gauge::default()
+            .block(Block::default().borders(Borders::ALL).title("Signal"))
+            .ratio(strength)
+            .label(&signal)
+            .style(gauge_style(strength))
+            .draw(rects[1], buf);
+
+        let rate = state.rate();
+        let rate_label = format!("{:.0} reads/s", rate);
+        Gauge::default()
+            .block(Block::default().borders(Borders::ALL).title("Read rate"))
+            .ratio((rate / MAX_RATE).min(1.0))
+            .label(&rate_label)
+            .style(gauge_style(rate / MAX_RATE))
+            .draw(rects[2], buf);
+
+        let last_seen = match &state.last_read {
+            Some(read) => format!(
+                "Last seen {:.1}s ago{}\n",
+                read.time.elapsed().as_secs_f64(),
+                match read.antenna {
+                    Some(antenna) => format!(" on antenna {}", antenna),
+                    None => "".to_string(),
+                }
+            ),
+            None => "Not seen yet\n".to_string(),
+        };
+        let text = [
+            Text::raw(last_seen),
+            Text::raw(format!(
+                "Bell {}. Press b to turn it {}, Esc to stop finding.\n",
+                if state.bell { "on" } else { "off" },
+                if state.bell { "off" } else { "on" }
+            )),
+        ];
+        Paragraph::new(text.iter()).draw(rects[3], buf);
+    }
+}
@@ -0,0 +1,137 @@
+//! User-supplied labels and categories for tags, saved between sessions.
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+/// Name of the labels file in the home directory, used unless another is given
+const DEFAULT_FILE: &str = ".epcexplorer_labels.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Annotation {
+    pub label: String,
+    #[serde(default)]
+    pub categories: Vec<String>,
+}
+
+impl Annotation {
+    /// Parse a label, where words starting with `#` are categories.
+    ///
+    /// Returns None if there's no label or categories.
+    pub fn parse(input: &str) -> Option<Annotation> {
+        let mut label = Vec::new();
+        let mut categories = Vec::new();
+        for word in input.split_whitespace() {
+            match word.strip_prefix('#') {
+                Some(category) if !category.is_empty() => categories.push(category.to_string()),
+                _ => label.push(word),
+            }
+        }
+        if label.is_empty() && categories.is_empty() {
+            return None;
+        }
+        Some(Annotation {
+            label: label.join(" "),
+            categories,
+        })
+    }
+
+    /// The annotation in the form accepted by `parse`.
+    pub fn to_input(&self) -> String {
+        let mut words = vec![self.label.to_owned()];
+        words.extend(self.categories.iter().map(|c| format!("#{}", c)));
+        words.retain(|word| !word.is_empty());
+        words.join(" ")
+    }
+
+    /// Whether the label or any category contains `search`, which should be lower case.
+    pub fn matches(&self, search: &str) -> bool {
+        self.label.to_lowercase().contains(search)
+            || self
+                .categories
+                .iter()
+                .any(|category| category.to_lowercase().contains(search))
+    }
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.label)?;
+        if !self.categories.is_empty() {
+            if !self.label.is_empty() {
+                write!(f, " ")?;
+            }
+            write!(f, "[{}]", self.categories.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Labels for tags, keyed by EPC
+pub(crate) struct Labels {
+    path: Option<String>,
+    labels: BTreeMap<String, Annotation>,
+}
+
+impl Labels {
+    /// Load labels from `path`, or the default file in the home directory if it's None.
+    pub fn load(path: Option<&str>) -> Result<Labels, failure::Error> {
+        let path = match path {
+            Some(path) => Some(path.to_string()),
+            None => env::var("HOME").ok().map(|home| {
+                Path::new(&home)
+                    .join(DEFAULT_FILE)
+                    .to_string_lossy()
+                    .into_owned()
+            }),
+        };
+        let labels = match &path {
+            Some(path) if Path::new(path).exists() => {
+                let file =
+                    File::open(path).map_err(|e| format_err!("Unable to open {}: {}", path, e))?;
+                serde_json::from_reader(BufReader::new(file))
+                    .map_err(|e| format_err!("{}: invalid labels: {}", path, e))?
+            }
+            _ => BTreeMap::new(),
+        };
+        Ok(Labels { path, labels })
+    }
+
+    /// Labels which aren't saved anywhere.
+    pub fn empty() -> Labels {
+        Labels {
+            path: None,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&self, epc: &[u8]) -> Option<&Annotation> {
+        self.labels.get(&hex::encode_upper(epc))
+    }
+
+    /// Set or remove the annotation for a tag, and save all labels.
+    pub fn set(
+        &mut self,
+        epc: &[u8],
+        annotation: Option<Annotation>,
+    ) -> Result<(), failure::Error> {
+        let key = hex::encode_upper(epc);
+        match annotation {
+            Some(annotation) => self.labels.insert(key, annotation),
+            None => self.labels.remove(&key),
+        };
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        // Write to a temporary file first, so the labels aren't lost if writing fails
+        let tmp_path = format!("{}.tmp", path);
+        serde_json::to_writer_pretty(File::create(&tmp_path)?, &self.labels)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
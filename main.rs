@@ -13,25 +13,52 @@ extern crate tui;
 extern crate backtrace;
 
 mod app;
+mod audit;
+mod config;
+mod drivers;
+mod encode;
+mod export;
+mod find;
+mod labels;
+mod memorydump;
+mod record;
+mod prompt;
 mod rfid;
+mod settings;
+mod statusbar;
+mod tagdb;
 mod tagdetail;
 mod tagtable;
 
+use std::collections::HashMap;
 use std::io;
+use std::io::Write;
 use std::panic;
 use std::sync::mpsc;
 use std::thread;
 use std::process;
 
 use crate::app::App;
-use crate::rfid::{scan_thread, ReaderType, ScanResult, ScanSettings};
+use crate::audit::{Audit, AuditView};
+use crate::config::Config;
+use crate::find::{FindState, FindView};
+use crate::labels::{Annotation, Labels};
+use crate::memorydump::{DumpState, MemoryDumpView};
+use crate::prompt::{Prompt, PromptAction, PromptKind, PromptView};
+use crate::rfid::{
+    scan_thread, Capabilities, EventSender, LockAction, LockTarget, Passwords, ReaderCommand,
+    ReaderEvent, ScanResult, ScanSettings, SelectMask,
+};
+use crate::settings::{SettingsState, SettingsView};
+use crate::statusbar::StatusBar;
+use crate::tagdb::TagDb;
 use crate::tagdetail::TagDetail;
 use crate::tagtable::TagTable;
 
 use clap::{App as Clap, Arg};
-use failure::bail;
-use log::error;
-use termion::event::Key;
+use failure::{bail, format_err};
+use log::{debug, error, warn};
+use termion::event::{Key, MouseButton, MouseEvent};
 use termion::input::MouseTerminal;
 use termion::raw::IntoRawMode;
 use termion::screen::AlternateScreen;
@@ -44,38 +71,514 @@ use backtrace::Backtrace;
 
 use crate::util::event::{Event, Events};
 
-fn init_rfid(
+/// Rows moved by each step of the mouse wheel
+const MOUSE_SCROLL_ROWS: isize = 3;
+
+/// Scan threads are named this, followed by the reader's number
+const SCAN_THREAD: &str = "scan";
+
+/// Channels to a reader's scan thread
+struct Connection {
+    /// The reader, as PORT:DRIVER
+    name: String,
+    commands_tx: mpsc::Sender<ReaderCommand>,
+    capabilities: Capabilities,
+    /// Antenna ports the reader has
+    antennas: Vec<u8>,
+    scan_thread: thread::JoinHandle<()>,
+}
+
+impl Connection {
+    /// Scan settings for this reader, leaving out antennas it doesn't have.
+    fn settings(&self, settings: &ScanSettings) -> ScanSettings {
+        ScanSettings {
+            antennas: settings.antennas.as_ref().map(|antennas| {
+                antennas
+                    .iter()
+                    .cloned()
+                    .filter(|antenna| self.antennas.contains(antenna))
+                    .collect()
+            }),
+            ..settings.to_owned()
+        }
+    }
+}
+
+/// The readers in the session, whose scan threads all send their events to `scan_rx`
+struct Connections {
+    scan_rx: mpsc::Receiver<ReaderEvent>,
+    readers: Vec<Connection>,
+}
+
+impl Connections {
+    /// Stop the scan threads, and wait for them to finish with the readers.
+    fn close(self) {
+        drop(self.scan_rx);
+        let threads: Vec<thread::JoinHandle<()>> = self
+            .readers
+            .into_iter()
+            .map(|reader| reader.scan_thread)
+            .collect();
+        for thread in threads {
+            if thread.join().is_err() {
+                warn!("Scan thread panicked");
+            }
+        }
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.readers.iter().map(|reader| reader.name.to_owned()).collect()
+    }
+
+    /// Antenna ports on any of the readers.
+    fn antennas(&self) -> Vec<u8> {
+        let mut antennas: Vec<u8> = self
+            .readers
+            .iter()
+            .flat_map(|reader| reader.antennas.iter().cloned())
+            .collect();
+        antennas.sort_unstable();
+        antennas.dedup();
+        antennas
+    }
+
+    /// The reader which last saw a tag, to send commands for the tag to.
+    fn for_tag(&self, items: &HashMap<Vec<u8>, ScanResult>, epc: &[u8]) -> &Connection {
+        &self.readers[items.get(epc).map_or(0, |item| item.reader)]
+    }
+
+    /// Send a command to every reader, returning whether they all took it.
+    fn send_all(&self, command: impl Fn(&Connection) -> ReaderCommand) -> bool {
+        self.readers
+            .iter()
+            .all(|reader| reader.commands_tx.send(command(reader)).is_ok())
+    }
+
+    /// Send a command to every reader without waiting for the replies, which `app` checks for
+    /// errors to show in the status bar. Returns whether they all took it.
+    fn send_all_unwaited<T: 'static>(
+        &self,
+        app: &mut App,
+        description: &'static str,
+        command: impl Fn(&Connection, mpsc::Sender<Result<T, failure::Error>>) -> ReaderCommand,
+    ) -> bool {
+        self.readers.iter().enumerate().all(|(i, reader)| {
+            let (reply, reply_rx) = mpsc::channel();
+            let sent = reader.commands_tx.send(command(reader, reply)).is_ok();
+            if sent {
+                app.expect_reply(i, description, reply_rx);
+            }
+            sent
+        })
+    }
+
+    /// Go back to the filter and settings from before finding a tag.
+    fn restore_scan(&self, app: &mut App) {
+        let filter = app.filter.to_owned();
+        let settings = app.settings.accepted.to_owned();
+        self.send_all_unwaited(app, "Restoring the filter", |_, reply| {
+            ReaderCommand::Select {
+                mask: filter.to_owned(),
+                reply,
+            }
+        });
+        self.send_all_unwaited(app, "Restoring the settings", |reader, reply| {
+            ReaderCommand::Settings {
+                settings: reader.settings(&settings),
+                reply,
+            }
+        });
+    }
+}
+
+/// Parse a reader given as PORT:DRIVER.
+fn parse_reader(spec: &str) -> Result<(String, String), failure::Error> {
+    match spec.rfind(':') {
+        Some(i) if drivers::DRIVERS.contains(&&spec[i + 1..]) => {
+            Ok((spec[..i].to_string(), spec[i + 1..].to_string()))
+        }
+        Some(i) => bail!("Unknown driver '{}' in reader '{}'", &spec[i + 1..], spec),
+        None => bail!("Reader '{}' must be given as PORT:DRIVER", spec),
+    }
+}
+
+fn connect(
+    index: usize,
     driver: &str,
     port: &str,
-) -> Result<(mpsc::Receiver<ScanResult>, mpsc::Sender<ScanSettings>), failure::Error> {
-    let reader_type = match driver {
-        "ru5102" => ReaderType::RU5102(ru5102::Reader::new(port)?),
-        "invelion" => ReaderType::Invelion(invelion::Reader::new(port, 1, 4)?),
-        _ => {
-            bail!("Invalid reader type (shouldn't happen)");
+    scan_tx: EventSender,
+    passwords: Passwords,
+    select: Option<&str>,
+) -> Result<Connection, failure::Error> {
+    let connector = drivers::Connector::new(driver, port)?;
+    let mut reader = connector.open()?;
+    let capabilities = reader.capabilities();
+    debug!("Reader {} capabilities: {:?}", index, capabilities);
+    let antennas = reader.antennas()?;
+
+    let (commands_tx, commands_rx) = mpsc::channel();
+    // Queued before the scan thread starts, so that it applies from the first inventory round
+    let (select_tx, select_rx) = mpsc::channel();
+    if let Some(input) = select {
+        let mask = parse_select(input, &capabilities)?;
+        commands_tx.send(ReaderCommand::Select {
+            mask,
+            reply: select_tx,
+        })?;
+    }
+    let scan_thread = thread::Builder::new()
+        .name(format!("{} {}", SCAN_THREAD, index + 1))
+        .spawn(move || {
+            scan_thread(reader, connector, scan_tx, commands_rx, passwords);
+        })?;
+    if select.is_some() {
+        select_rx.recv()??;
+    }
+    Ok(Connection {
+        name: format!("{}:{}", port, driver),
+        commands_tx,
+        capabilities,
+        antennas,
+        scan_thread,
+    })
+}
+
+/// Open each of the (PORT, DRIVER) `readers`, and start a scan thread for it.
+fn init_rfid(
+    readers: &[(String, String)],
+    record_path: Option<&str>,
+    passwords: Passwords,
+    select: Option<&str>,
+) -> Result<Connections, failure::Error> {
+    let (scan_tx, scan_rx) = mpsc::channel();
+    let mut connections = Vec::new();
+    for (index, (port, driver)) in readers.iter().enumerate() {
+        let tx = EventSender::new(index, scan_tx.clone());
+        match connect(index, driver, port, tx, passwords.clone(), select) {
+            Ok(connection) => connections.push(connection),
+            Err(err) => {
+                // Stop the readers which have already started
+                drop(scan_tx);
+                Connections {
+                    scan_rx,
+                    readers: connections,
+                }
+                .close();
+                return Err(match readers.len() {
+                    1 => err,
+                    _ => format_err!("{}:{}: {}", port, driver, err),
+                });
+            }
         }
+    }
+    drop(scan_tx);
+    let scan_rx = match record_path {
+        Some(path) => record::record(scan_rx, path)?,
+        None => scan_rx,
     };
+    Ok(Connections {
+        scan_rx,
+        readers: connections,
+    })
+}
 
-    let (scan_tx, scan_rx) = mpsc::channel();
-    let (settings_tx, settings_rx) = mpsc::channel();
-    thread::spawn(move || {
-        scan_thread(reader_type, scan_tx, settings_rx);
-    });
-    Ok((scan_rx, settings_tx))
+/// Parse a Select mask and check that it can be used with this reader.
+fn parse_select(
+    input: &str,
+    capabilities: &Capabilities,
+) -> Result<Option<SelectMask>, failure::Error> {
+    let mask = encode::parse_select(input)?;
+    if let Some(mask) = &mask {
+        rfid::check_select(mask, capabilities)?;
+    }
+    Ok(mask)
+}
+
+/// Send a command which replies with a result to `readers`, and show its progress in the prompt.
+fn start_command(
+    prompt: &mut Prompt,
+    readers: &[&Connection],
+    command: impl Fn(mpsc::Sender<Result<(), failure::Error>>) -> ReaderCommand,
+    waiting: &str,
+    done: &str,
+) {
+    let (reply, reply_rx) = mpsc::channel();
+    for reader in readers {
+        if reader.commands_tx.send(command(reply.clone())).is_err() {
+            prompt.message = Some(format!("Reader {} stopped", reader.name));
+            return;
+        }
+    }
+    prompt.start(reply_rx, readers.len(), waiting, done);
+}
+
+/// Parse a password entered in a prompt, where empty means the tag's stored password.
+fn prompt_password(input: &str) -> Result<Option<u32>, failure::Error> {
+    if input.trim().is_empty() {
+        Ok(None)
+    } else {
+        rfid::parse_password(input).map(Some)
+    }
+}
+
+/// Act on the input to a prompt, returning the next prompt if there's another step.
+fn submit_prompt(
+    prompt: &mut Prompt,
+    input: &str,
+    connections: &Connections,
+    items: &mut HashMap<Vec<u8>, ScanResult>,
+    filter: &mut Option<SelectMask>,
+) -> Option<Prompt> {
+    let all: Vec<&Connection> = connections.readers.iter().collect();
+    match prompt.kind.to_owned() {
+        PromptKind::WriteEPC(epc) => match encode::parse_epc(input) {
+            Ok(new_epc) => start_command(
+                prompt,
+                &[connections.for_tag(items, &epc)],
+                |reply| ReaderCommand::WriteEPC {
+                    epc: epc.to_owned(),
+                    new_epc: new_epc.to_owned(),
+                    reply,
+                },
+                "Writing...",
+                "EPC written and verified",
+            ),
+            Err(err) => {
+                prompt.message = Some(err.to_string());
+            }
+        },
+        PromptKind::Lock(epc) => {
+            let words: Vec<&str> = input.split_whitespace().collect();
+            match words.as_slice() {
+                [target, action] => match (LockTarget::parse(target), LockAction::parse(action)) {
+                    (Some(target), Some(action)) => {
+                        let mut next = Prompt::new(
+                            PromptKind::LockPassword {
+                                epc,
+                                target,
+                                action,
+                            },
+                            "Access password (8 hex digits, empty to use the tag's password)",
+                            "",
+                        );
+                        next.secret = true;
+                        return Some(next);
+                    }
+                    (None, _) => prompt.message = Some(format!("Unknown target '{}'", target)),
+                    (_, None) => prompt.message = Some(format!("Unknown action '{}'", action)),
+                },
+                _ => prompt.message = Some("Enter a target and an action".to_string()),
+            }
+        }
+        PromptKind::LockPassword {
+            epc,
+            target,
+            action,
+        } => match prompt_password(input) {
+            Ok(password) if action.is_permanent() => {
+                let title = format!(
+                    "{} will be {} FOREVER. Type 'permanent' to confirm",
+                    target, action
+                );
+                return Some(Prompt::new(
+                    PromptKind::ConfirmLock {
+                        epc,
+                        target,
+                        action,
+                        password,
+                    },
+                    &title,
+                    "",
+                ));
+            }
+            Ok(password) => {
+                let done = format!("{} {}", target, action);
+                start_command(
+                    prompt,
+                    &[connections.for_tag(items, &epc)],
+                    |reply| ReaderCommand::Lock {
+                        epc: epc.to_owned(),
+                        target,
+                        action,
+                        password,
+                        reply,
+                    },
+                    "Locking...",
+                    &done,
+                )
+            }
+            Err(err) => {
+                prompt.message = Some(err.to_string());
+            }
+        },
+        PromptKind::ConfirmLock {
+            epc,
+            target,
+            action,
+            password,
+        } => {
+            if input.trim() == "permanent" {
+                let done = format!("{} {}", target, action);
+                start_command(
+                    prompt,
+                    &[connections.for_tag(items, &epc)],
+                    |reply| ReaderCommand::Lock {
+                        epc: epc.to_owned(),
+                        target,
+                        action,
+                        password,
+                        reply,
+                    },
+                    "Locking...",
+                    &done,
+                )
+            } else {
+                prompt.message = Some("Not confirmed. Esc to cancel".to_string());
+            }
+        }
+        PromptKind::Password(epc) => match prompt_password(input) {
+            Ok(password) => {
+                // Every reader might see the tag next
+                let sent = connections.send_all(|_| ReaderCommand::SetPassword {
+                    epc: epc.to_owned(),
+                    password,
+                });
+                // Whether the tag allowed access with the old password doesn't matter any more
+                if let Some(item) = items.get_mut(&epc) {
+                    item.access = None;
+                }
+                prompt.message = Some(match (sent, password) {
+                    (false, _) => "Reader stopped".to_string(),
+                    (true, Some(_)) => "Access password set for this tag".to_string(),
+                    (true, None) => "Using the default access password".to_string(),
+                });
+            }
+            Err(err) => {
+                prompt.message = Some(err.to_string());
+            }
+        },
+        PromptKind::Search(_) | PromptKind::Label(_) | PromptKind::ExportAudit => {}
+        PromptKind::Select => match connections
+            .readers
+            .iter()
+            .map(|reader| parse_select(input, &reader.capabilities))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(masks) => {
+                let mask = masks.into_iter().next().flatten();
+                let in_reader = connections
+                    .readers
+                    .iter()
+                    .filter(|reader| reader.capabilities.select)
+                    .count();
+                let done = match &mask {
+                    None => "Filter cleared",
+                    Some(_) if in_reader == all.len() => "Filtering in the reader",
+                    Some(_) if in_reader == 0 => "Filtering in software",
+                    Some(_) => "Filtering in the readers which support it, and in software",
+                };
+                *filter = mask.clone();
+                start_command(
+                    prompt,
+                    &all,
+                    |reply| ReaderCommand::Select {
+                        mask: mask.clone(),
+                        reply,
+                    },
+                    "Setting filter...",
+                    done,
+                )
+            }
+            Err(err) => {
+                prompt.message = Some(err.to_string());
+            }
+        },
+    }
+    None
+}
+
+/// Look for readers and list them, returning the PORT and DRIVER of the reader to use if exactly
+/// one usable reader responded.
+fn detect_reader(
+    port: Option<&str>,
+    driver: Option<&str>,
+) -> Result<Option<(String, String)>, failure::Error> {
+    eprintln!("Looking for readers...");
+    let found = drivers::detect::detect(port, driver)?;
+    for reader in &found {
+        eprintln!("  {}", reader);
+    }
+    let mut usable = found.iter().filter(|reader| reader.usable);
+    match (usable.next(), usable.next()) {
+        (Some(reader), None) => {
+            eprintln!("Using {} {}", reader.spec(), reader.driver);
+            Ok(Some((reader.spec(), reader.driver.to_string())))
+        }
+        (None, _) if found.is_empty() => bail!("No readers found"),
+        (None, _) => bail!("No usable readers found"),
+        _ => {
+            eprintln!("More than one reader found, give PORT and DRIVER to choose one");
+            Ok(None)
+        }
+    }
 }
 
 fn main() -> Result<(), failure::Error> {
     let matches = Clap::new("EPC Explorer")
         .arg(
             Arg::with_name("PORT")
-                .help("Serial port for reader")
-                .required(true),
+                .help("Serial port for reader (or options for the sim driver, or file for replay)")
+                .required_unless_one(&["detect", "config", "reader"]),
         )
         .arg(
             Arg::with_name("DRIVER")
                 .help("Driver to use")
-                .possible_values(&["ru5102", "invelion"])
-                .required(true),
+                .possible_values(drivers::DRIVERS)
+                .required_unless_one(&["detect", "config", "reader"]),
+        )
+        .arg(
+            Arg::with_name("reader")
+                .long("reader")
+                .value_name("PORT:DRIVER")
+                .help("Also scan with another reader at the same time (can be given more than once)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("detect")
+                .long("detect")
+                .help("Look for a reader on the serial ports (or PORT), and use it if there's one"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read the reader's PORT, DRIVER and driver options from FILE (see README)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("baud")
+                .long("baud")
+                .value_name("BAUD")
+                .help("Baud rate the reader has been set to, if not its default")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("address")
+                .long("address")
+                .value_name("ADDRESS")
+                .help("Address of a reader on PORT's RS-485 bus (can be given more than once)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("ports")
+                .long("ports")
+                .value_name("N")
+                .help("Number of antenna ports the reader has, if not the driver's default")
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("log")
@@ -84,6 +587,72 @@ fn main() -> Result<(), failure::Error> {
                 .help("Write debug logs to files in DIRECTORY")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("FILE")
+                .help("Record scan results to FILE, for use with the replay driver")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("password")
+                .long("password")
+                .value_name("PASSWORD")
+                .help("Access password for tag memory operations, as 8 hex digits")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("select")
+                .long("select")
+                .value_name("MASK")
+                .help("Only inventory tags matching MASK (an EPC prefix in hex, see README)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("db")
+                .long("db")
+                .value_name("FILE")
+                .help("Keep a database of every tag seen, across sessions, in FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("labels")
+                .long("labels")
+                .value_name("FILE")
+                .help("Keep tag labels in FILE (default ~/.epcexplorer_labels.json)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("expected")
+                .long("expected")
+                .value_name("FILE")
+                .help("Audit the tags seen against the EPCs or URIs listed in the CSV FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("FORMAT")
+                .help("Scan without the interactive display and output the tags found")
+                .possible_values(export::FORMATS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("duration")
+                .long("duration")
+                .value_name("DURATION")
+                .help("How long to scan for with --output (e.g. 30s, 5m)")
+                .default_value("10s")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-file")
+                .long("output-file")
+                .value_name("FILE")
+                .help("Write --output to FILE instead of stdout")
+                .requires("output")
+                .takes_value(true),
+        )
         .get_matches();
 
     if let Some(log_dir) = matches.value_of("log") {
@@ -95,10 +664,80 @@ fn main() -> Result<(), failure::Error> {
             .unwrap();
     }
 
-    let (scan_rx, _settings_tx) = init_rfid(
-        matches.value_of("DRIVER").unwrap(),
-        matches.value_of("PORT").unwrap(),
+    let passwords = Passwords {
+        default: rfid::parse_password(matches.value_of("password").unwrap_or(""))?,
+        ..Passwords::default()
+    };
+    let mut readers = Vec::new();
+    if matches.is_present("detect") {
+        match detect_reader(matches.value_of("PORT"), matches.value_of("DRIVER"))? {
+            Some(reader) => readers.push(reader),
+            None => return Ok(()),
+        }
+    } else if matches.is_present("config") || matches.is_present("PORT") {
+        let config = match matches.value_of("config") {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        readers.push(config.reader(matches.value_of("PORT"), matches.value_of("DRIVER"))?);
+    }
+    if matches.is_present("reader")
+        && ["baud", "address", "ports"]
+            .iter()
+            .any(|name| matches.is_present(name))
+    {
+        bail!(
+            "--baud, --address and --ports can't be used with --reader, add ',baud=N', \
+             ',address=N' or ',ports=N' to each reader's PORT instead"
+        );
+    }
+    if let Some((port, _)) = readers.first_mut() {
+        if let Some(baud) = matches.value_of("baud") {
+            port.push_str(&format!(",baud={}", baud));
+        }
+        for address in matches.values_of("address").into_iter().flatten() {
+            port.push_str(&format!(",address={}", address));
+        }
+        if let Some(ports) = matches.value_of("ports") {
+            port.push_str(&format!(",ports={}", ports));
+        }
+    }
+    for spec in matches.values_of("reader").into_iter().flatten() {
+        readers.push(parse_reader(spec)?);
+    }
+    let names: Vec<String> = readers
+        .iter()
+        .map(|(port, driver)| format!("{}:{}", port, driver))
+        .collect();
+    let db = match matches.value_of("db") {
+        Some(path) => Some(TagDb::open(path, &names)?),
+        None => None,
+    };
+    let expected = match matches.value_of("expected") {
+        Some(path) => audit::load(path)?,
+        None => Vec::new(),
+    };
+    let connections = init_rfid(
+        &readers,
+        matches.value_of("record"),
+        passwords,
+        matches.value_of("select"),
     )?;
+    let scan_rx = &connections.scan_rx;
+
+    if let Some(format) = matches.value_of("output") {
+        let result = export::run(
+            scan_rx,
+            connections.names(),
+            format,
+            export::parse_duration(matches.value_of("duration").unwrap())?,
+            matches.value_of("output-file"),
+            db,
+            expected,
+        );
+        connections.close();
+        return result;
+    }
 
     panic::set_hook(Box::new(panic_hook));
 
@@ -111,45 +750,415 @@ fn main() -> Result<(), failure::Error> {
 
     let events = Events::new();
 
-    let mut app = App::new();
+    let mut app = App::new(connections.names());
+    app.db = db;
+    app.labels = Labels::load(matches.value_of("labels"))?;
+    app.expected = expected;
+    if let Some(input) = matches.value_of("select") {
+        app.filter = encode::parse_select(input)?;
+    }
+    let session = connections
+        .readers
+        .iter()
+        .any(|reader| reader.capabilities.session);
+    let max_power = connections
+        .readers
+        .iter()
+        .filter_map(|reader| reader.capabilities.max_power)
+        .max();
+    app.settings = SettingsState::new(
+        ScanSettings::default(),
+        connections.antennas(),
+        session,
+        max_power,
+    );
 
     loop {
         terminal.draw(|mut f| {
+            let mut constraints = vec![Constraint::Min(0)];
+            if app.prompt.is_some() {
+                constraints.push(Constraint::Length(4));
+            }
+            constraints.push(Constraint::Percentage(20));
+            constraints.push(Constraint::Length(app.readers.len() as u16));
+            let rects = Layout::default()
+                .constraints(constraints.as_slice())
+                .split(f.size());
+            let rows = tagtable::rows_area(rects[0]);
             let items = app.get_items();
+            let scroll = app.scroll_to_selected(&items, rows);
             let selected = app.selected.to_owned();
-            let selected_item = match selected {
-                Some(epc) => app.items.get(&epc),
+            let selected_item = match &selected {
+                Some(epc) => app.items.get(epc),
                 None => None,
             };
-            let rects = Layout::default()
-                .constraints([Constraint::Percentage(80), Constraint::Percentage(20)].as_ref())
-                .split(f.size());
-            TagTable::new(&items, app.selected.to_owned()).render(&mut f, rects[0]);
-            TagDetail::new(selected_item).render(&mut f, rects[1]);
+            let selected_stats = match &selected {
+                Some(epc) => app.stats.get(epc),
+                None => None,
+            };
+            match &app.dump {
+                Some(dump) => MemoryDumpView::new(dump).render(&mut f, rects[0]),
+                None if app.find.is_some() => {
+                    FindView::new(app.find.as_ref().unwrap()).render(&mut f, rects[0])
+                }
+                None if app.show_settings => {
+                    SettingsView::new(&app.settings).render(&mut f, rects[0])
+                }
+                None if app.show_audit => {
+                    let audit = Audit::new(&app);
+                    AuditView::new(&audit, app.audit_scroll).render(&mut f, rects[0])
+                }
+                None => TagTable::new(&items, &app, scroll).render(&mut f, rects[0]),
+            }
+            if let Some(prompt) = &app.prompt {
+                PromptView::new(prompt).render(&mut f, rects[1]);
+            }
+            let selected_label = match &selected {
+                Some(epc) => app.labels.get(epc),
+                None => None,
+            };
+            TagDetail::new(selected_item, selected_stats, app.db.as_ref(), selected_label)
+                .render(&mut f, rects[rects.len() - 2]);
+            StatusBar::new(&app.status, &app.readers).render(&mut f, rects[rects.len() - 1]);
+            app.scroll = scroll;
+            app.table_rows = rows;
         })?;
 
         match events.next()? {
+            Event::Input(key) if app.prompt.is_some() => {
+                let prompt = app.prompt.as_mut().unwrap();
+                let action = prompt.handle_key(key);
+                if let PromptKind::Search(previous) = &prompt.kind {
+                    // The table is searched as the text is typed
+                    match action {
+                        PromptAction::Cancel => {
+                            app.search = previous.to_owned();
+                            app.prompt = None;
+                        }
+                        PromptAction::Submit(_) => {
+                            app.prompt = None;
+                        }
+                        PromptAction::None => {
+                            app.search = Some(prompt.input.to_owned())
+                                .filter(|search| !search.is_empty());
+                        }
+                    }
+                    continue;
+                }
+                if let (PromptKind::Label(epc), PromptAction::Submit(input)) =
+                    (&prompt.kind, &action)
+                {
+                    let annotation = Annotation::parse(input);
+                    let done = if annotation.is_some() {
+                        "Label saved"
+                    } else {
+                        "Label removed"
+                    };
+                    prompt.message = Some(match app.labels.set(epc, annotation) {
+                        Ok(()) => done.to_string(),
+                        Err(err) => format!("Failed: {}", err),
+                    });
+                    continue;
+                }
+                if let (PromptKind::ExportAudit, PromptAction::Submit(path)) =
+                    (&prompt.kind, &action)
+                {
+                    let message = match audit::write_file(&Audit::new(&app), path) {
+                        Ok(()) => format!("Audit written to {}", path),
+                        Err(err) => format!("Failed: {}", err),
+                    };
+                    app.prompt.as_mut().unwrap().message = Some(message);
+                    continue;
+                }
+                match action {
+                    PromptAction::Cancel => {
+                        app.prompt = None;
+                    }
+                    PromptAction::Submit(input) => {
+                        if let Some(next) = submit_prompt(
+                            prompt,
+                            &input,
+                            &connections,
+                            &mut app.items,
+                            &mut app.filter,
+                        ) {
+                            app.prompt = Some(next);
+                        }
+                    }
+                    PromptAction::None => {}
+                }
+            }
+            Event::Input(key) if app.dump.is_some() => {
+                let dump = app.dump.as_mut().unwrap();
+                match key {
+                    Key::Esc | Key::Char('m') => {
+                        app.dump = None;
+                    }
+                    Key::Char('q') | Key::Ctrl('c') => {
+                        break;
+                    }
+                    Key::Up => {
+                        dump.scroll = dump.scroll.saturating_sub(1);
+                    }
+                    Key::Down => {
+                        dump.scroll += 1;
+                    }
+                    _ => {}
+                }
+            }
+            Event::Input(key) if app.find.is_some() => match key {
+                Key::Esc | Key::Char('g') => {
+                    app.find = None;
+                    connections.restore_scan(&mut app);
+                }
+                Key::Char('q') | Key::Ctrl('c') => {
+                    break;
+                }
+                Key::Char('b') => {
+                    let find = app.find.as_mut().unwrap();
+                    find.bell = !find.bell;
+                }
+                _ => {}
+            },
+            Event::Input(key) if app.show_settings => match key {
+                Key::Esc | Key::Char('s') => {
+                    app.show_settings = false;
+                }
+                Key::Char('q') | Key::Ctrl('c') => {
+                    break;
+                }
+                Key::Up => app.settings.select(true),
+                Key::Down => app.settings.select(false),
+                Key::Left => app.settings.change(false),
+                Key::Right => app.settings.change(true),
+                Key::Char('\n') => {
+                    let (reply, reply_rx) = mpsc::channel();
+                    let sent = connections.send_all(|reader| ReaderCommand::Settings {
+                        settings: reader.settings(&app.settings.requested),
+                        reply: reply.clone(),
+                    });
+                    if sent {
+                        app.settings.start(reply_rx, connections.readers.len());
+                    }
+                }
+                _ => {}
+            },
+            Event::Input(key) if app.show_audit => match key {
+                Key::Esc | Key::Char('e') => {
+                    app.show_audit = false;
+                }
+                Key::Char('q') | Key::Ctrl('c') => {
+                    break;
+                }
+                Key::Char('x') => {
+                    app.prompt = Some(Prompt::new(
+                        PromptKind::ExportAudit,
+                        "Export the audit to (.csv or .json)",
+                        "audit.csv",
+                    ));
+                }
+                Key::Up => {
+                    app.scroll_audit(-1);
+                }
+                Key::Down => {
+                    app.scroll_audit(1);
+                }
+                Key::PageUp => {
+                    app.scroll_audit(-app.page_size());
+                }
+                Key::PageDown => {
+                    app.scroll_audit(app.page_size());
+                }
+                _ => {}
+            },
             Event::Input(key) => match key {
-                Key::Char('q') => {
+                Key::Char('q') | Key::Ctrl('c') => {
                     break;
                 }
+                Key::Char('s') => {
+                    app.show_settings = true;
+                }
+                Key::Char('e') => {
+                    app.show_audit = true;
+                }
+                Key::Char('g') => {
+                    if let Some(epc) = app.selected.to_owned() {
+                        // Only look for this tag, on every reader, and skip detailed scans to
+                        // read it as often as possible
+                        let mask = SelectMask::for_epc(&epc);
+                        let settings = ScanSettings {
+                            detailed_scan: false,
+                            ..app.settings.accepted.to_owned()
+                        };
+                        let select = connections.send_all_unwaited(
+                            &mut app,
+                            "Filtering for the tag to find",
+                            |_, reply| ReaderCommand::Select {
+                                mask: Some(mask.to_owned()),
+                                reply,
+                            },
+                        );
+                        let settings = connections.send_all_unwaited(
+                            &mut app,
+                            "Turning off detailed scans to find the tag",
+                            |reader, reply| ReaderCommand::Settings {
+                                settings: reader.settings(&settings),
+                                reply,
+                            },
+                        );
+                        if select && settings {
+                            app.find = Some(FindState::new(&epc));
+                        } else {
+                            // Don't leave the readers which took the commands only looking for
+                            // this tag
+                            connections.restore_scan(&mut app);
+                        }
+                    }
+                }
+                Key::Char('m') => {
+                    if let Some(epc) = &app.selected {
+                        let (reply, reply_rx) = mpsc::channel();
+                        let command = ReaderCommand::ReadBanks {
+                            epc: epc.to_owned(),
+                            reply,
+                        };
+                        let reader = connections.for_tag(&app.items, epc);
+                        if reader.commands_tx.send(command).is_ok() {
+                            app.dump = Some(DumpState::new(epc, reply_rx));
+                        }
+                    }
+                }
+                Key::Char('w') => {
+                    if let Some(epc) = &app.selected {
+                        app.prompt = Some(Prompt::new(
+                            PromptKind::WriteEPC(epc.to_owned()),
+                            "Write EPC (hex or tag URI)",
+                            &hex::encode_upper(epc),
+                        ));
+                    }
+                }
+                Key::Char('l') => {
+                    if let Some(epc) = &app.selected {
+                        app.prompt = Some(Prompt::new(
+                            PromptKind::Lock(epc.to_owned()),
+                            "Lock: kill|access|epc|tid|user unlock|lock|permaunlock|permalock",
+                            "",
+                        ));
+                    }
+                }
+                Key::Char('p') => {
+                    if let Some(epc) = &app.selected {
+                        let mut prompt = Prompt::new(
+                            PromptKind::Password(epc.to_owned()),
+                            "Access password for this tag (8 hex digits, empty for the default)",
+                            "",
+                        );
+                        prompt.secret = true;
+                        app.prompt = Some(prompt);
+                    }
+                }
+                Key::Char('a') => {
+                    if let Some(epc) = &app.selected {
+                        let input = app.labels.get(epc).map(Annotation::to_input);
+                        app.prompt = Some(Prompt::new(
+                            PromptKind::Label(epc.to_owned()),
+                            "Label, with #words as categories (empty to remove)",
+                            &input.unwrap_or_default(),
+                        ));
+                    }
+                }
+                Key::Char('f') => {
+                    app.prompt = Some(Prompt::new(
+                        PromptKind::Select,
+                        "Filter: hex EPC prefix, bank:hex[@bit], gs1:company or \
+                         sgtin-96:filter.company (empty to clear)",
+                        "",
+                    ));
+                }
                 Key::Char('i') => {
                     app.show_inactive = !app.show_inactive;
                 }
+                Key::Char('o') => {
+                    app.next_sort(false);
+                }
+                Key::Char('O') => {
+                    app.next_sort(true);
+                }
+                Key::Char('v') => {
+                    app.next_reader_filter();
+                }
+                Key::Char('r') => {
+                    app.sort_reverse = !app.sort_reverse;
+                }
+                Key::Char('/') => {
+                    let search = app.search.to_owned();
+                    app.prompt = Some(Prompt::new(
+                        PromptKind::Search(search.to_owned()),
+                        "Search EPC, URI, label, manufacturer or model (Enter to keep, Esc to cancel)",
+                        &search.unwrap_or_default(),
+                    ));
+                }
                 Key::Down => {
                     app.update_selected(false);
                 }
                 Key::Up => {
                     app.update_selected(true);
                 }
+                Key::PageDown => {
+                    app.move_selected(app.page_size());
+                }
+                Key::PageUp => {
+                    app.move_selected(-app.page_size());
+                }
+                Key::Home => {
+                    app.select_first();
+                }
+                Key::End => {
+                    app.select_last();
+                }
                 _ => {}
             },
+            Event::Mouse(mouse) => {
+                let table_shown =
+                    app.dump.is_none() && app.find.is_none() && !app.show_settings && !app.show_audit;
+                match mouse {
+                    MouseEvent::Press(MouseButton::WheelDown, _, _) if table_shown => {
+                        app.move_selected(MOUSE_SCROLL_ROWS);
+                    }
+                    MouseEvent::Press(MouseButton::WheelUp, _, _) if table_shown => {
+                        app.move_selected(-MOUSE_SCROLL_ROWS);
+                    }
+                    // Mouse coordinates start at 1
+                    MouseEvent::Press(MouseButton::Left, x, y) if table_shown => {
+                        app.click(x.saturating_sub(1), y.saturating_sub(1));
+                    }
+                    _ => {}
+                }
+            }
             Event::Tick => {
-                app.update_items(&scan_rx);
+                app.update_items(scan_rx);
+                if let Some(prompt) = &mut app.prompt {
+                    prompt.poll();
+                }
+                if let Some(dump) = &mut app.dump {
+                    dump.poll();
+                }
+                app.settings.poll();
+                if let Some(db) = &mut app.db {
+                    db.poll();
+                }
+                if app.find.as_mut().is_some_and(|find| find.poll()) {
+                    print!("\x07");
+                    io::stdout().flush()?;
+                }
             }
         };
     }
 
+    if let Some(db) = &mut app.db {
+        db.flush()?;
+    }
+    connections.close();
     Ok(())
 }
 
@@ -160,7 +1169,7 @@ pub(crate) fn block<'a>(title: &'a str) -> Block<'a> {
         .title_style(Style::default().fg(Color::Red))
 }
 
-fn panic_hook(info: &panic::PanicInfo<'_>) {
+fn panic_hook(info: &panic::PanicHookInfo<'_>) {
     let backtrace = Backtrace::new();
     let thread = thread::current();
     let thread = thread.name().unwrap_or("unnamed");
@@ -191,6 +1200,10 @@ fn panic_hook(info: &panic::PanicInfo<'_>) {
             backtrace
         ),
     }
+    // The UI can carry on without a scan thread, and shows that its reader has stopped
+    if thread.starts_with(SCAN_THREAD) {
+        return;
+    }
     println!(
         "{}thread '<unnamed>' panicked at '{}'. See log for more info.\r",
         termion::screen::ToMainScreen,
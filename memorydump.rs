@@ -0,0 +1,241 @@
+use crate::block;
+use crate::rfid::{
+    to_word, BankDump, ExtendedProtocolControl, MemoryBank, MemoryDump, ProtocolControl,
+};
+use gs1::epc;
+use gs1::epc::tid::{decode_tid, decode_xtid_header, mdid_name, tmid_name};
+use std::sync::mpsc;
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Paragraph, Text, Widget};
+
+/// Number of unannotated words shown on each line
+const WORDS_PER_LINE: usize = 8;
+
+/// State of the memory dump view
+pub(crate) struct DumpState {
+    pub epc: Vec<u8>,
+    pending: Option<mpsc::Receiver<MemoryDump>>,
+    pub dump: Option<MemoryDump>,
+    pub scroll: u16,
+}
+
+impl DumpState {
+    pub fn new(epc: &[u8], reply: mpsc::Receiver<MemoryDump>) -> DumpState {
+        DumpState {
+            epc: epc.to_owned(),
+            pending: Some(reply),
+            dump: None,
+            scroll: 0,
+        }
+    }
+
+    /// Check whether the memory has finished being read.
+    pub fn poll(&mut self) {
+        if let Some(rx) = &self.pending {
+            match rx.try_recv() {
+                Ok(dump) => {
+                    self.dump = Some(dump);
+                    self.pending = None;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.pending = None;
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+            }
+        }
+    }
+}
+
+/// A labelled range of words within a bank
+struct Region {
+    start: usize,
+    words: usize,
+    description: String,
+}
+
+fn word(data: &[u8], index: usize) -> Option<u16> {
+    data.get(index * 2..).and_then(to_word)
+}
+
+fn region(start: usize, words: usize, description: String) -> Region {
+    Region {
+        start,
+        words,
+        description,
+    }
+}
+
+fn annotate_epc_bank(data: &[u8]) -> Vec<Region> {
+    let mut regions = vec![region(0, 1, "StoredCRC".to_string())];
+    let pc = match word(data, 1) {
+        Some(pc) => ProtocolControl::decode(pc),
+        None => return regions,
+    };
+    let length = pc.length as usize;
+    regions.push(region(1, 1, format!("StoredPC: {}", pc)));
+    let epc_end = 2 + length;
+    let description = match data.get(4..epc_end * 2) {
+        Some(epc) => match epc::decode_binary(epc) {
+            Ok(val) => format!("EPC ({})", val.to_uri()),
+            Err(_) => "EPC".to_string(),
+        },
+        None => "EPC".to_string(),
+    };
+    regions.push(region(2, length, description));
+    if let (true, Some(xpc_w1)) = (pc.xi, word(data, 0x21)) {
+        regions.push(region(
+            0x21,
+            1,
+            format!("XPC_W1: {}", ExtendedProtocolControl::decode(xpc_w1)),
+        ));
+    }
+    regions
+}
+
+fn annotate_tid_bank(data: &[u8]) -> Vec<Region> {
+    let tid = match data.get(0..4).map(decode_tid) {
+        Some(Ok(tid)) => tid,
+        _ => return vec![],
+    };
+    let mut regions = vec![region(
+        0,
+        2,
+        format!(
+            "TID: {} {} (MDID 0x{:X}, TMID 0x{:X})",
+            mdid_name(&tid.mdid),
+            tmid_name(tid.mdid, tid.tmid),
+            tid.mdid,
+            tid.tmid
+        ),
+    )];
+    if !tid.xtid {
+        return regions;
+    }
+    let xtid = match data.get(4..6).map(decode_xtid_header) {
+        Some(Ok(xtid)) => xtid,
+        _ => return regions,
+    };
+    let mut segments = Vec::new();
+    if xtid.optional_command_support {
+        segments.push("optional commands");
+    }
+    if xtid.blockwrite_blockerase {
+        segments.push("BlockWrite/BlockErase");
+    }
+    if xtid.user_memory_permalock {
+        segments.push("user memory/BlockPermaLock");
+    }
+    regions.push(region(
+        2,
+        1,
+        format!(
+            "XTID header: {} bit serial{}{}",
+            xtid.serial_size,
+            if segments.is_empty() { "" } else { ", " },
+            segments.join(", ")
+        ),
+    ));
+    if xtid.serial_size > 0 {
+        regions.push(region(3, xtid.serial_size as usize / 16, "Serial".to_string()));
+    }
+    regions
+}
+
+fn annotate(bank: &BankDump) -> Vec<Region> {
+    match bank.bank {
+        MemoryBank::Reserved => vec![
+            region(0, 2, "Kill password".to_string()),
+            region(2, 2, "Access password".to_string()),
+        ],
+        MemoryBank::EPC => annotate_epc_bank(&bank.data),
+        MemoryBank::TID => annotate_tid_bank(&bank.data),
+        MemoryBank::User => vec![],
+    }
+}
+
+fn hex_words(data: &[u8], start: usize, end: usize) -> String {
+    (start..end)
+        .filter_map(|i| word(data, i))
+        .map(|w| format!("{:04X}", w))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn render_bank(bank: &BankDump) -> Vec<Text<'static>> {
+    let words = bank.data.len() / 2;
+    let mut text = vec![Text::styled(
+        format!("{:?} bank ({} words)\n", bank.bank, words),
+        Style::default().modifier(Modifier::BOLD),
+    )];
+
+    let regions = annotate(bank);
+    let mut i = 0;
+    while i < words {
+        match regions.iter().find(|r| r.start == i && r.words > 0) {
+            Some(region) => {
+                let end = std::cmp::min(i + region.words, words);
+                text.push(Text::raw(format!(
+                    "  {:04X}  {}  ",
+                    i,
+                    hex_words(&bank.data, i, end)
+                )));
+                text.push(Text::styled(
+                    format!("{}\n", region.description),
+                    Style::default().fg(Color::Cyan),
+                ));
+                i = end;
+            }
+            None => {
+                let next_region = regions
+                    .iter()
+                    .map(|r| r.start)
+                    .filter(|start| *start > i)
+                    .min()
+                    .unwrap_or(words);
+                let end = *[i + WORDS_PER_LINE, next_region, words].iter().min().unwrap();
+                text.push(Text::raw(format!(
+                    "  {:04X}  {}\n",
+                    i,
+                    hex_words(&bank.data, i, end)
+                )));
+                i = end;
+            }
+        }
+    }
+    if let Some(error) = &bank.error {
+        text.push(Text::styled(
+            format!("  Read stopped: {}\n", error),
+            Style::default().fg(Color::Gray),
+        ));
+    }
+    text.push(Text::raw("\n"));
+    text
+}
+
+pub(crate) struct MemoryDumpView<'a> {
+    pub state: &'a DumpState,
+}
+
+impl<'a> MemoryDumpView<'a> {
+    pub fn new(state: &'a DumpState) -> MemoryDumpView<'a> {
+        MemoryDumpView { state }
+    }
+}
+
+impl<'a> Widget for MemoryDumpView<'a> {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        let text = match &self.state.dump {
+            Some(dump) => dump.banks.iter().flat_map(render_bank).collect(),
+            None if self.state.pending.is_some() => vec![Text::raw("Reading memory...")],
+            None => vec![Text::raw("Reader stopped")],
+        };
+        let title = format!("Memory: {}", hex::encode_upper(&self.state.epc));
+
+        Paragraph::new(text.iter())
+            .block(block(&title))
+            .scroll(self.state.scroll)
+            .draw(area, buf);
+    }
+}
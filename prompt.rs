@@ -0,0 +1,161 @@
+use crate::block;
+use crate::rfid::{LockAction, LockTarget};
+use std::sync::mpsc;
+use termion::event::Key;
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::{Color, Style};
+use tui::widgets::{Paragraph, Text, Widget};
+
+/// What a prompt's input will be used for
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum PromptKind {
+    /// New EPC for the tag with this EPC
+    WriteEPC(Vec<u8>),
+    /// Area of memory and lock action for the tag with this EPC
+    Lock(Vec<u8>),
+    /// Access password to use for a lock action
+    LockPassword {
+        epc: Vec<u8>,
+        target: LockTarget,
+        action: LockAction,
+    },
+    /// Confirmation of a permanent lock action
+    ConfirmLock {
+        epc: Vec<u8>,
+        target: LockTarget,
+        action: LockAction,
+        password: Option<u32>,
+    },
+    /// Access password for the tag with this EPC
+    Password(Vec<u8>),
+    /// Select mask to filter inventory
+    Select,
+    /// Text to search the tag table for, with the search from before the prompt was opened
+    Search(Option<String>),
+    /// Label and `#categories` for the tag with this EPC
+    Label(Vec<u8>),
+    /// File to export the audit to
+    ExportAudit,
+}
+
+/// Result of a key press in a prompt
+pub(crate) enum PromptAction {
+    None,
+    Cancel,
+    Submit(String),
+}
+
+/// A single-line text input, along with the progress of the operation it started
+pub(crate) struct Prompt {
+    pub kind: PromptKind,
+    pub title: String,
+    pub input: String,
+    pub message: Option<String>,
+    /// Hide the input, for passwords
+    pub secret: bool,
+    pending: Option<mpsc::Receiver<Result<(), failure::Error>>>,
+    /// Replies still to come for the pending operation, one from each reader it was sent to
+    replies: usize,
+    /// Message to show when the pending operation succeeds
+    done: String,
+}
+
+impl Prompt {
+    pub fn new(kind: PromptKind, title: &str, input: &str) -> Prompt {
+        Prompt {
+            kind,
+            title: title.to_string(),
+            input: input.to_string(),
+            message: None,
+            secret: false,
+            pending: None,
+            replies: 0,
+            done: String::new(),
+        }
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> PromptAction {
+        match key {
+            Key::Esc => return PromptAction::Cancel,
+            Key::Char('\n') if self.pending.is_none() => {
+                return PromptAction::Submit(self.input.to_owned());
+            }
+            Key::Char('\n') => {}
+            Key::Backspace => {
+                self.input.pop();
+            }
+            Key::Char(c) => {
+                self.input.push(c);
+            }
+            _ => {}
+        }
+        PromptAction::None
+    }
+
+    /// Show `waiting` until `replies` results of an operation arrive, then `done` if they all
+    /// succeeded.
+    pub fn start(
+        &mut self,
+        reply: mpsc::Receiver<Result<(), failure::Error>>,
+        replies: usize,
+        waiting: &str,
+        done: &str,
+    ) {
+        self.pending = Some(reply);
+        self.replies = replies;
+        self.message = Some(waiting.to_string());
+        self.done = done.to_string();
+    }
+
+    /// Check whether the pending operation has finished.
+    pub fn poll(&mut self) {
+        let result = match &self.pending {
+            Some(rx) => match rx.try_recv() {
+                Ok(Ok(())) if self.replies > 1 => {
+                    self.replies -= 1;
+                    return;
+                }
+                Ok(result) => result,
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => Err(failure::err_msg("Reader stopped")),
+            },
+            None => return,
+        };
+        self.pending = None;
+        self.message = Some(match result {
+            Ok(()) => self.done.to_owned(),
+            Err(err) => format!("Failed: {}", err),
+        });
+    }
+}
+
+pub(crate) struct PromptView<'a> {
+    pub prompt: &'a Prompt,
+}
+
+impl<'a> PromptView<'a> {
+    pub fn new(prompt: &'a Prompt) -> PromptView<'a> {
+        PromptView { prompt }
+    }
+}
+
+impl<'a> Widget for PromptView<'a> {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        let input = if self.prompt.secret {
+            "*".repeat(self.prompt.input.chars().count())
+        } else {
+            self.prompt.input.to_owned()
+        };
+        let mut text = vec![Text::raw(format!("> {}_\n", input))];
+        if let Some(message) = &self.prompt.message {
+            text.push(Text::styled(message, Style::default().fg(Color::Yellow)));
+        } else {
+            text.push(Text::raw("Enter to confirm, Esc to cancel"));
+        }
+
+        Paragraph::new(text.iter())
+            .block(block(&self.prompt.title))
+            .draw(area, buf);
+    }
+}
@@ -0,0 +1,227 @@
+//! Recording of scan sessions, for later replay with the `replay` driver.
+//!
+//! Recordings are stored as JSON, one `RecordEntry` per line.
+use crate::rfid::{ReaderEvent, ScanEvent, ScanResult};
+use failure::format_err;
+use gs1::epc::tid::{XTIDHeader, TID};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RecordedTID {
+    xtid: bool,
+    security: bool,
+    file: bool,
+    mdid: u16,
+    tmid: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RecordedXTIDHeader {
+    extended_header: bool,
+    user_memory_permalock: bool,
+    blockwrite_blockerase: bool,
+    optional_command_support: bool,
+    serial_size: u16,
+}
+
+impl RecordedTID {
+    pub fn new(tid: &TID) -> RecordedTID {
+        RecordedTID {
+            xtid: tid.xtid,
+            security: tid.security,
+            file: tid.file,
+            mdid: tid.mdid,
+            tmid: tid.tmid,
+        }
+    }
+
+    pub fn to_tid(&self) -> TID {
+        TID {
+            xtid: self.xtid,
+            security: self.security,
+            file: self.file,
+            mdid: self.mdid,
+            tmid: self.tmid,
+        }
+    }
+}
+
+impl RecordedXTIDHeader {
+    pub fn new(xtid: &XTIDHeader) -> RecordedXTIDHeader {
+        RecordedXTIDHeader {
+            extended_header: xtid.extended_header,
+            user_memory_permalock: xtid.user_memory_permalock,
+            blockwrite_blockerase: xtid.blockwrite_blockerase,
+            optional_command_support: xtid.optional_command_support,
+            serial_size: xtid.serial_size,
+        }
+    }
+
+    pub fn to_xtid_header(&self) -> XTIDHeader {
+        XTIDHeader {
+            extended_header: self.extended_header,
+            user_memory_permalock: self.user_memory_permalock,
+            blockwrite_blockerase: self.blockwrite_blockerase,
+            optional_command_support: self.optional_command_support,
+            serial_size: self.serial_size,
+        }
+    }
+}
+
+fn default_read() -> bool {
+    true
+}
+
+/// A serialisable `ScanResult`
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RecordEntry {
+    /// Milliseconds since the start of the session
+    pub time: u64,
+    epc: String,
+    /// Older recordings don't say, so everything in them counts as a read
+    #[serde(default = "default_read")]
+    pub read: bool,
+    #[serde(default)]
+    pc: Option<u16>,
+    #[serde(default)]
+    xpc_w1: Option<u16>,
+    tid: Option<RecordedTID>,
+    xtid_header: Option<RecordedXTIDHeader>,
+    serial: Option<String>,
+    rssi: Option<i8>,
+    pub antenna: Option<u8>,
+    /// Index of the reader which saw the tag, which is 0 in recordings of a single reader
+    #[serde(default)]
+    pub reader: usize,
+}
+
+impl RecordEntry {
+    pub fn from_result(result: &ScanResult, start: time::Instant) -> RecordEntry {
+        RecordEntry {
+            time: result
+                .last_seen
+                .checked_duration_since(start)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            epc: hex::encode_upper(&result.epc),
+            read: result.read,
+            pc: result.pc,
+            xpc_w1: result.xpc_w1,
+            tid: result.tid.as_ref().map(RecordedTID::new),
+            xtid_header: result.xtid_header.as_ref().map(RecordedXTIDHeader::new),
+            serial: result.serial.as_ref().map(hex::encode_upper),
+            rssi: result.rssi,
+            antenna: result.antenna,
+            reader: result.reader,
+        }
+    }
+
+    /// Convert back to a `ScanResult`, seen now.
+    pub fn to_result(&self) -> Result<ScanResult, failure::Error> {
+        let mut result = ScanResult::from_epc(hex::decode(&self.epc)?);
+        result.read = self.read;
+        result.pc = self.pc;
+        result.xpc_w1 = self.xpc_w1;
+        result.tid = self.tid.as_ref().map(RecordedTID::to_tid);
+        result.xtid_header = self.xtid_header.as_ref().map(RecordedXTIDHeader::to_xtid_header);
+        result.serial = match &self.serial {
+            Some(serial) => Some(hex::decode(serial)?),
+            None => None,
+        };
+        result.rssi = self.rssi;
+        result.antenna = self.antenna;
+        result.reader = self.reader;
+        Ok(result)
+    }
+}
+
+/// Load all entries from a recording.
+pub(crate) fn load(path: &str) -> Result<Vec<RecordEntry>, failure::Error> {
+    let file = File::open(path).map_err(|e| format_err!("Unable to open {}: {}", path, e))?;
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(
+            serde_json::from_str(&line)
+                .map_err(|e| format_err!("{}:{}: invalid record: {}", path, i + 1, e))?,
+        );
+    }
+    Ok(entries)
+}
+
+/// Write every result received on `rx` to `path`, passing it and any other events on to the
+/// returned receiver.
+pub(crate) fn record(
+    rx: mpsc::Receiver<ReaderEvent>,
+    path: &str,
+) -> Result<mpsc::Receiver<ReaderEvent>, failure::Error> {
+    let file = File::create(path).map_err(|e| format_err!("Unable to create {}: {}", path, e))?;
+    let mut writer = BufWriter::new(file);
+    let start = time::Instant::now();
+    let (tx, out_rx) = mpsc::channel();
+    thread::spawn(move || {
+        for event in rx {
+            if let ScanEvent::Tag(result) = &event.event {
+                let entry = RecordEntry::from_result(result, start);
+                if let Err(err) = serde_json::to_writer(&mut writer, &entry)
+                    .map_err(failure::Error::from)
+                    .and_then(|_| Ok(writer.write_all(b"\n")?))
+                    .and_then(|_| Ok(writer.flush()?))
+                {
+                    warn!("Error writing recording: {:?}", err);
+                }
+            }
+            if tx.send(event).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(out_rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_results() {
+        let mut tag = ScanResult::from_read(hex::decode("E2801160600002054D7E01F2").unwrap());
+        tag.pc = Some(0x3200);
+        tag.xpc_w1 = Some(0x0400);
+        tag.tid = Some(TID {
+            xtid: true,
+            security: false,
+            file: false,
+            mdid: 0x801,
+            tmid: 0x160,
+        });
+        tag.xtid_header = Some(XTIDHeader {
+            extended_header: false,
+            user_memory_permalock: false,
+            blockwrite_blockerase: true,
+            optional_command_support: false,
+            serial_size: 48,
+        });
+        tag.serial = Some(vec![0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC]);
+        tag.rssi = Some(-52);
+        tag.antenna = Some(3);
+        tag.reader = 1;
+
+        let start = tag.last_seen - time::Duration::from_millis(1500);
+        let line = serde_json::to_string(&RecordEntry::from_result(&tag, start)).unwrap();
+        let entry: RecordEntry = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry.time, 1500);
+        let mut result = entry.to_result().unwrap();
+        result.last_seen = tag.last_seen;
+        assert_eq!(result, tag);
+    }
+}
@@ -1,53 +1,727 @@
-use gs1::epc::tid::{decode_tid, decode_xtid_header, XTIDHeader, TID};
-use log::warn;
-//use std::collections::HashSet;
+use crate::drivers::Connector;
+use failure::{bail, format_err, Fail};
+use gs1::epc::tid::{decode_tid, decode_xtid_header, mdid_name, tmid_name, XTIDHeader, TID};
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
 use std::sync::mpsc;
+use std::thread;
 use std::time;
-use log::debug;
 
-pub(crate) enum ReaderType {
-    Invelion(invelion::Reader),
-    RU5102(ru5102::Reader),
+/// Gen2 memory banks
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) enum MemoryBank {
+    Reserved,
+    EPC,
+    TID,
+    User,
 }
 
+/// All memory banks, in order
+pub(crate) const MEMORY_BANKS: [MemoryBank; 4] = [
+    MemoryBank::Reserved,
+    MemoryBank::EPC,
+    MemoryBank::TID,
+    MemoryBank::User,
+];
+
+/// Reads of a bank will give up after this many words, in case a tag never reports an overrun.
+const MAX_BANK_WORDS: u8 = 255;
+
+/// Time to wait after a failed inventory round before trying again
+const ERROR_BACKOFF: time::Duration = time::Duration::from_millis(500);
+/// Inventory rounds which can fail in a row before the connection is assumed to be lost
+const MAX_FAILURES: u32 = 5;
+/// Time between attempts to reconnect to a reader
+const RECONNECT_INTERVAL: time::Duration = time::Duration::from_secs(2);
+/// How often to check the reader's health
+const HEALTH_INTERVAL: time::Duration = time::Duration::from_secs(10);
+
+/// The contents of one memory bank, as far as it could be read
+#[derive(Debug, Clone)]
+pub(crate) struct BankDump {
+    pub bank: MemoryBank,
+    pub data: Vec<u8>,
+    /// The error which ended the read, which is usually a memory overrun
+    pub error: Option<String>,
+}
+
+/// The contents of all memory banks of a tag
+#[derive(Debug, Clone)]
+pub(crate) struct MemoryDump {
+    pub banks: Vec<BankDump>,
+}
+
+/// Areas of tag memory which can be locked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) enum LockTarget {
+    KillPassword,
+    AccessPassword,
+    EPC,
+    TID,
+    User,
+}
+
+impl LockTarget {
+    pub fn parse(name: &str) -> Option<LockTarget> {
+        match name.to_lowercase().as_str() {
+            "kill" => Some(LockTarget::KillPassword),
+            "access" => Some(LockTarget::AccessPassword),
+            "epc" => Some(LockTarget::EPC),
+            "tid" => Some(LockTarget::TID),
+            "user" => Some(LockTarget::User),
+            _ => None,
+        }
+    }
+
+    /// The lock target covering a word of memory.
+    pub fn for_memory(bank: MemoryBank, word: u8) -> LockTarget {
+        match bank {
+            MemoryBank::Reserved if word < 2 => LockTarget::KillPassword,
+            MemoryBank::Reserved => LockTarget::AccessPassword,
+            MemoryBank::EPC => LockTarget::EPC,
+            MemoryBank::TID => LockTarget::TID,
+            MemoryBank::User => LockTarget::User,
+        }
+    }
+}
+
+impl fmt::Display for LockTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LockTarget::KillPassword => "Kill password",
+            LockTarget::AccessPassword => "Access password",
+            LockTarget::EPC => "EPC bank",
+            LockTarget::TID => "TID bank",
+            LockTarget::User => "User bank",
+        })
+    }
+}
+
+/// Gen2 lock actions.
+///
+/// A locked password can only be read or written, and a locked bank only written, once the tag
+/// has been accessed with its access password. Permanent actions can never be changed.
+///
+/// # Reference
+/// EPC UHF Gen2 Air Interface Protocol Section 6.3.2.11.3.5
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LockAction {
+    Unlock,
+    Lock,
+    PermaUnlock,
+    PermaLock,
+}
+
+impl LockAction {
+    pub fn parse(name: &str) -> Option<LockAction> {
+        match name.to_lowercase().as_str() {
+            "unlock" => Some(LockAction::Unlock),
+            "lock" => Some(LockAction::Lock),
+            "permaunlock" => Some(LockAction::PermaUnlock),
+            "permalock" => Some(LockAction::PermaLock),
+            _ => None,
+        }
+    }
+
+    pub fn is_permanent(self) -> bool {
+        self == LockAction::PermaUnlock || self == LockAction::PermaLock
+    }
+}
+
+impl fmt::Display for LockAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LockAction::Unlock => "unlocked",
+            LockAction::Lock => "locked",
+            LockAction::PermaUnlock => "permanently unlocked",
+            LockAction::PermaLock => "permanently locked",
+        })
+    }
+}
+
+/// Parse a 32-bit access or kill password from hex. An empty password is zero.
+pub(crate) fn parse_password(input: &str) -> Result<u32, failure::Error> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(0);
+    }
+    if input.len() != 8 {
+        bail!("Password must be 8 hex digits");
+    }
+    u32::from_str_radix(input, 16).map_err(|_| format_err!("Password must be 8 hex digits"))
+}
+
+/// A tag refused access to its memory because of its access password
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AccessError {
+    PasswordRequired,
+    WrongPassword,
+}
+
+impl Fail for AccessError {}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AccessError::PasswordRequired => "Access denied: password required",
+            AccessError::WrongPassword => "Access denied: incorrect access password",
+        })
+    }
+}
+
+impl AccessError {
+    /// The error for a tag refusing access when `password` was used.
+    pub fn for_password(password: u32) -> AccessError {
+        if password == 0 {
+            AccessError::PasswordRequired
+        } else {
+            AccessError::WrongPassword
+        }
+    }
+}
+
+/// An error from the scan thread, which carries on scanning after it
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ScanError {
+    /// An inventory round failed
+    Inventory(String),
+    /// Reading the details of the tags found failed
+    Details(String),
+    /// Checking the reader's health failed
+    Health(String),
+    /// Connecting to the reader again failed
+    Connection(String),
+    /// A command sent without waiting for its reply failed, with a description of the command
+    Command(&'static str, String),
+}
+
+impl Fail for ScanError {}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanError::Inventory(err) => write!(f, "Inventory failed: {}", err),
+            ScanError::Details(err) => write!(f, "Reading tag details failed: {}", err),
+            ScanError::Health(err) => write!(f, "Checking reader health failed: {}", err),
+            ScanError::Connection(err) => write!(f, "Reconnecting failed: {}", err),
+            ScanError::Command(command, err) => write!(f, "{} failed: {}", command, err),
+        }
+    }
+}
+
+/// A message from the scan thread
+#[derive(Debug, Clone)]
+pub(crate) enum ScanEvent {
+    Tag(ScanResult),
+    /// An inventory round finished, with this many reads
+    Round(usize),
+    Error(ScanError),
+    /// The reader is connected, and this is its latest health
+    Connected(ReaderHealth),
+    /// The connection to the reader was lost, and this is the attempt to reconnect
+    Reconnecting(u32),
+    /// The scan thread has finished, so there will be no more events from the reader
+    Stopped,
+}
+
+/// An event from one of the readers in the session
+#[derive(Debug, Clone)]
+pub(crate) struct ReaderEvent {
+    /// Index of the reader, in the order readers were given
+    pub reader: usize,
+    pub event: ScanEvent,
+}
+
+/// Sends events from a reader's scan thread to the channel shared by all readers, marking the
+/// tags it sees with the reader's index.
+///
+/// `ScanEvent::Stopped` is sent when this is dropped, even if the scan thread panics, as the
+/// channel stays open while other readers are using it.
+pub(crate) struct EventSender {
+    reader: usize,
+    tx: mpsc::Sender<ReaderEvent>,
+}
+
+impl EventSender {
+    pub fn new(reader: usize, tx: mpsc::Sender<ReaderEvent>) -> EventSender {
+        EventSender { reader, tx }
+    }
+
+    /// Send an event, or `Stop::Unreceived` if nothing is receiving them any more.
+    fn send(&self, mut event: ScanEvent) -> Result<(), Stop> {
+        if let ScanEvent::Tag(tag) = &mut event {
+            tag.reader = self.reader;
+        }
+        self.tx
+            .send(ReaderEvent {
+                reader: self.reader,
+                event,
+            })
+            .map_err(|_| Stop::Unreceived)
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        let _ = self.send(ScanEvent::Stopped);
+    }
+}
+
+/// Information about the reader itself, where it reports it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ReaderHealth {
+    pub firmware: Option<String>,
+    /// Degrees Celsius
+    pub temperature: Option<i8>,
+}
+
+/// Access passwords to use for memory operations
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Passwords {
+    /// Password for tags which don't have their own
+    pub default: u32,
+    /// Passwords for individual tags, by EPC
+    pub tags: HashMap<Vec<u8>, u32>,
+}
+
+impl Passwords {
+    pub fn get(&self, epc: &[u8]) -> u32 {
+        *self.tags.get(epc).unwrap_or(&self.default)
+    }
+}
+
+/// Bit address of the start of the EPC in the EPC bank, after the CRC and PC words
+pub(crate) const EPC_START_BIT: u16 = 0x20;
+
+/// A Gen2 Select mask: tags match if `length` bits of `mask` appear at bit `pointer` of `bank`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SelectMask {
+    pub bank: MemoryBank,
+    pub pointer: u16,
+    pub mask: Vec<u8>,
+    pub length: u16,
+}
+
+fn bit(data: &[u8], index: usize) -> Option<bool> {
+    data.get(index / 8).map(|byte| byte & (0x80 >> (index % 8)) != 0)
+}
+
+impl SelectMask {
+    /// A mask matching tags whose EPC starts with `epc`.
+    pub fn for_epc(epc: &[u8]) -> SelectMask {
+        SelectMask {
+            bank: MemoryBank::EPC,
+            pointer: EPC_START_BIT,
+            mask: epc.to_owned(),
+            length: epc.len() as u16 * 8,
+        }
+    }
+
+    /// Whether the mask matches the contents of a bank, read from its start.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        let pointer = self.pointer as usize;
+        (0..self.length as usize)
+            .all(|i| bit(data, pointer + i).is_some_and(|b| Some(b) == bit(&self.mask, i)))
+    }
+
+    /// Whether a tag with this EPC matches, if that can be worked out from the EPC alone.
+    pub fn matches_epc(&self, epc: &[u8]) -> Option<bool> {
+        if self.bank != MemoryBank::EPC || self.pointer < EPC_START_BIT {
+            return None;
+        }
+        let mask = SelectMask {
+            pointer: self.pointer - EPC_START_BIT,
+            ..self.clone()
+        };
+        Some(mask.matches(epc))
+    }
+}
+
+impl fmt::Display for SelectMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} bank bit 0x{:X}: {}/{}",
+            self.bank,
+            self.pointer,
+            hex::encode_upper(&self.mask),
+            self.length
+        )
+    }
+}
+
+/// Optional features supported by a reader driver
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct Capabilities {
+    /// The reader reports RSSI for inventoried tags
+    pub rssi: bool,
+    /// The reader has more than one antenna port and reports which one saw a tag
+    pub antennas: bool,
+    /// The reader can restrict inventory with a Select mask
+    pub select: bool,
+    /// The reader can be set to inventory with a given session, target and Q
+    pub session: bool,
+    /// Highest TX power in dBm the reader can be set to, if its power can be set
+    pub max_power: Option<u8>,
+}
+
+/// A UHF RFID reader.
+///
+/// Each driver implements this so that `scan_thread` doesn't need to know which reader it's
+/// talking to.
+pub(crate) trait Reader: Send {
+    /// Optional features supported by this reader.
+    fn capabilities(&self) -> Capabilities;
+
+    /// List the antenna ports the reader has, which scan settings choose from to inventory on.
+    fn antennas(&mut self) -> Result<Vec<u8>, failure::Error>;
+
+    /// Firmware version and temperature, if the reader reports them.
+    fn health(&mut self) -> Result<ReaderHealth, failure::Error> {
+        Ok(ReaderHealth::default())
+    }
+
+    /// Inventory all tags in the reader's range.
+    fn inventory(&mut self) -> Result<Vec<ScanResult>, failure::Error>;
+
+    /// Read `words` 2-byte words from a memory bank of the tag with the given EPC, accessing
+    /// the tag with `password` if it's not zero.
+    ///
+    /// Drivers should return an `AccessError` if the tag refuses access because of its password.
+    fn read_memory(
+        &mut self,
+        epc: &[u8],
+        bank: MemoryBank,
+        start: u8,
+        words: u8,
+        password: u32,
+    ) -> Result<Vec<u8>, failure::Error>;
+
+    /// Write 2-byte words to a memory bank of the tag with the given EPC.
+    fn write_memory(
+        &mut self,
+        _epc: &[u8],
+        _bank: MemoryBank,
+        _start: u8,
+        _data: &[u8],
+        _password: u32,
+    ) -> Result<(), failure::Error> {
+        bail!("Writing is not supported by this reader")
+    }
+
+    /// Apply scan settings, returning the settings which the reader accepted.
+    ///
+    /// The default implementation accepts only the settings which are handled by `scan_thread`.
+    fn configure(&mut self, settings: &ScanSettings) -> Result<ScanSettings, failure::Error> {
+        Ok(ScanSettings {
+            detailed_scan: settings.detailed_scan,
+            ..ScanSettings::default()
+        })
+    }
+
+    /// Restrict inventory to tags matching a Select mask, or all tags if it's None.
+    ///
+    /// Only called if the reader's capabilities include `select`.
+    fn select(&mut self, _mask: Option<&SelectMask>) -> Result<(), failure::Error> {
+        bail!("Select is not supported by this reader")
+    }
+
+    /// Lock or unlock an area of memory of the tag with the given EPC, accessing the tag with
+    /// `password` first.
+    fn lock(
+        &mut self,
+        _epc: &[u8],
+        _target: LockTarget,
+        _action: LockAction,
+        _password: u32,
+    ) -> Result<(), failure::Error> {
+        bail!("Locking is not supported by this reader")
+    }
+
+    /// Read TID, XTID header and serial for tags which have been inventoried.
+    ///
+    /// The default implementation reads each tag individually. Drivers for readers which can
+    /// read from all tags in the field at once may override this.
+    fn details(
+        &mut self,
+        tags: &[ScanResult],
+        passwords: &Passwords,
+    ) -> Result<Vec<ScanResult>, failure::Error> {
+        Ok(tags
+            .iter()
+            .map(|tag| read_tag_details(self, tag, passwords.get(&tag.epc)))
+            .collect())
+    }
+}
+
+/// Gen2 inventory session
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Session {
+    S0,
+    S1,
+    S2,
+    S3,
+}
+
+/// Gen2 inventoried flag to inventory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Target {
+    A,
+    B,
+    /// Alternate between A and B on each round
+    AB,
+}
+
+/// Settings for the scan thread and reader.
+///
+/// Settings which are None are left as the reader has them, which is also what drivers report
+/// for settings they don't support.
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) struct ScanSettings {
+    /// Read TID and other details of inventoried tags
     pub detailed_scan: bool,
+    /// Antenna ports to inventory on
+    pub antennas: Option<Vec<u8>>,
+    /// Transmit power in dBm
+    pub power: Option<u8>,
+    pub session: Option<Session>,
+    pub target: Option<Target>,
+    /// Initial Q value, for 2^Q slots in each inventory round
+    pub q: Option<u8>,
+    /// Time each inventory round takes, over all the antennas in use
+    pub round_duration: Option<time::Duration>,
 }
 
 impl ScanSettings {
     pub fn default() -> ScanSettings {
         ScanSettings {
             detailed_scan: true,
+            antennas: None,
+            power: None,
+            session: None,
+            target: None,
+            q: None,
+            round_duration: None,
+        }
+    }
+}
+
+/// Requests from the UI to the scan thread
+pub(crate) enum ReaderCommand {
+    /// Change scan settings, replying with the settings which the reader accepted
+    Settings {
+        settings: ScanSettings,
+        reply: mpsc::Sender<Result<ScanSettings, failure::Error>>,
+    },
+    /// Change the EPC of a tag, replying once the new EPC has been verified
+    WriteEPC {
+        epc: Vec<u8>,
+        new_epc: Vec<u8>,
+        reply: mpsc::Sender<Result<(), failure::Error>>,
+    },
+    /// Read the whole of every memory bank of a tag
+    ReadBanks {
+        epc: Vec<u8>,
+        reply: mpsc::Sender<MemoryDump>,
+    },
+    /// Lock or unlock an area of a tag's memory
+    Lock {
+        epc: Vec<u8>,
+        target: LockTarget,
+        action: LockAction,
+        /// Access password, or None to use the tag's stored password
+        password: Option<u32>,
+        reply: mpsc::Sender<Result<(), failure::Error>>,
+    },
+    /// Set the access password for a tag, or go back to the default password if None
+    SetPassword { epc: Vec<u8>, password: Option<u32> },
+    /// Restrict inventory to tags matching a Select mask, or all tags if None
+    Select {
+        mask: Option<SelectMask>,
+        reply: mpsc::Sender<Result<(), failure::Error>>,
+    },
+}
+
+/// The StoredPC word from the EPC bank
+///
+/// # Reference
+/// EPC UHF Gen2 Air Interface Protocol Section 6.3.2.1.2.2
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ProtocolControl {
+    /// Length of the EPC in words
+    pub length: u8,
+    /// User memory indicator
+    pub umi: bool,
+    /// XPC_W1 indicator
+    pub xi: bool,
+    /// Numbering system toggle: set for an ISO AFI, clear for a GS1 EPC
+    pub toggle: bool,
+    /// The AFI if the toggle is set, otherwise the GS1 attribute bits
+    pub nsi: u8,
+}
+
+impl ProtocolControl {
+    pub fn decode(pc: u16) -> ProtocolControl {
+        ProtocolControl {
+            length: (pc >> 11) as u8,
+            umi: pc & 0x0400 != 0,
+            xi: pc & 0x0200 != 0,
+            toggle: pc & 0x0100 != 0,
+            nsi: pc as u8,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} word EPC", self.length)?;
+        if self.umi {
+            write!(f, ", user memory")?;
+        }
+        if self.xi {
+            write!(f, ", XPC")?;
+        }
+        if self.toggle {
+            write!(f, ", ISO AFI 0x{:02X}", self.nsi)
+        } else {
+            write!(f, ", GS1 attributes 0x{:02X}", self.nsi)
+        }
+    }
+}
+
+/// Flags in XPC_W1 after XEB and three RFU bits, with their abbreviations and meanings
+const XPC_W1_FLAGS: &[(u16, &str, &str)] = &[
+    (0x0800, "SA", "sensor alarm"),
+    (0x0400, "SS", "simple sensor"),
+    (0x0200, "FS", "full sensor"),
+    (0x0100, "SN", "snapshot sensor"),
+    (0x0080, "B", "battery assisted"),
+    (0x0040, "C", "computed response"),
+    (0x0020, "SLI", "SL flag set"),
+    (0x0010, "TN", "tag notification"),
+    (0x0008, "U", "untraceable"),
+    (0x0004, "K", "killable"),
+    (0x0002, "NR", "nonremovable"),
+    (0x0001, "H", "hazmat"),
+];
+
+/// The XPC_W1 word from the EPC bank, which follows the EPC if the PC's XI bit is set
+///
+/// # Reference
+/// EPC UHF Gen2 Air Interface Protocol Section 6.3.2.1.2.5
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ExtendedProtocolControl {
+    /// XPC_W2 indicator
+    pub xeb: bool,
+    /// Abbreviations and meanings of the other flags which are set
+    pub flags: Vec<(&'static str, &'static str)>,
+}
+
+impl ExtendedProtocolControl {
+    pub fn decode(xpc_w1: u16) -> ExtendedProtocolControl {
+        ExtendedProtocolControl {
+            xeb: xpc_w1 & 0x8000 != 0,
+            flags: XPC_W1_FLAGS
+                .iter()
+                .filter(|(mask, _, _)| xpc_w1 & mask != 0)
+                .map(|(_, name, meaning)| (*name, *meaning))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for ExtendedProtocolControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEB={}", self.xeb as u8)?;
+        for (name, meaning) in &self.flags {
+            write!(f, ", {} ({})", name, meaning)?;
         }
+        Ok(())
+    }
+}
+
+/// Convert two big-endian bytes read from a tag into a word.
+pub(crate) fn to_word(data: &[u8]) -> Option<u16> {
+    match data {
+        [msb, lsb, ..] => Some(u16::from(*msb) << 8 | u16::from(*lsb)),
+        _ => None,
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct ScanResult {
     pub epc: Vec<u8>,
+    /// Whether the tag was read in an inventory round, rather than this being the result of a
+    /// detailed scan or other operation on the tag
+    pub read: bool,
+    /// StoredPC word
+    pub pc: Option<u16>,
+    /// XPC_W1 word, if the PC's XI bit is set
+    pub xpc_w1: Option<u16>,
+    /// Lock actions which have been applied to the tag
+    pub locks: BTreeMap<LockTarget, LockAction>,
+    /// Result of the last operation which needed access to the tag's memory
+    pub access: Option<Result<(), AccessError>>,
     pub tid: Option<TID>,
     pub xtid_header: Option<XTIDHeader>,
     pub serial: Option<Vec<u8>>,
     pub rssi: Option<i8>,
     pub antenna: Option<u8>,
+    /// Index of the reader which last saw the tag, in the order readers were given
+    pub reader: usize,
     pub last_seen: time::Instant,
 }
 
 impl ScanResult {
     pub fn from_epc(epc: Vec<u8>) -> ScanResult {
         ScanResult {
-            epc: epc,
+            epc,
+            read: false,
+            pc: None,
+            xpc_w1: None,
+            locks: BTreeMap::new(),
+            access: None,
             tid: None,
             xtid_header: None,
             serial: None,
             rssi: None,
             antenna: None,
+            reader: 0,
             last_seen: time::Instant::now(),
         }
     }
 
+    /// A result for a tag read in an inventory round.
+    pub fn from_read(epc: Vec<u8>) -> ScanResult {
+        ScanResult {
+            read: true,
+            ..ScanResult::from_epc(epc)
+        }
+    }
+
+    /// The tag chip's manufacturer, from its TID.
+    pub fn manufacturer(&self) -> Option<String> {
+        self.tid.map(|tid| mdid_name(&tid.mdid).to_string())
+    }
+
+    /// The tag chip's model from its TID, or the model number if it isn't known.
+    pub fn model(&self) -> Option<String> {
+        self.tid.map(|tid| match tmid_name(tid.mdid, tid.tmid) {
+            "Unknown" => format!("0x{:X}", tid.tmid),
+            found => found.to_string(),
+        })
+    }
+
     pub fn update(&mut self, other: ScanResult) {
         assert_eq!(self.epc, other.epc);
+        self.pc = other.pc.or(self.pc);
+        self.xpc_w1 = other.xpc_w1.or(self.xpc_w1);
+        self.locks.extend(other.locks);
+        self.access = other.access.or(self.access);
         self.tid = match other.tid {
             Some(tid) => Some(tid),
             None => self.tid,
@@ -60,174 +734,792 @@ impl ScanResult {
             Some(serial) => Some(serial),
             None => self.serial.to_owned(),
         };
-        self.rssi = match other.rssi {
-            Some(rssi) => Some(rssi),
-            None => self.rssi
-        };
-        self.antenna = other.antenna;
-        self.last_seen = other.last_seen;
-    }
-}
-
-fn read_tid(
-    reader: &mut ru5102::Reader,
-    uid: &Vec<u8>,
-    start: u8,
-    words: u8,
-) -> Result<Vec<u8>, ru5102::error::Error> {
-    let read_cmd = ru5102::ReadCommand {
-        epc: uid.to_owned(),
-        location: ru5102::MemoryLocation::TID,
-        start_address: start,
-        count: words,
-        password: None,
-        mask_address: None,
-        mask_length: None,
-    };
+        // Other results, such as from accessing the tag's memory, don't say where or when it was
+        // last seen
+        if other.read {
+            self.rssi = match other.rssi {
+                Some(rssi) => Some(rssi),
+                None => self.rssi,
+            };
+            self.antenna = other.antenna;
+            self.reader = other.reader;
+            self.last_seen = other.last_seen;
+        }
+    }
+}
 
-    reader.read_data(read_cmd)
+/// Decode the TID structure, logging rather than failing if it's malformed.
+pub(crate) fn parse_tid(data: &[u8]) -> Option<TID> {
+    match decode_tid(data) {
+        Ok(tid) => Some(tid),
+        Err(_) => {
+            warn!("decode_tid error: {:?}", data);
+            None
+        }
+    }
 }
 
-fn get_details_ru5102(
-    tags: &[ScanResult],
-    reader: &mut ru5102::Reader,
-) -> Vec<ScanResult> {
-    tags.iter().map(|tag| get_tag_details_ru5102(tag, reader)).collect()
+/// The `AccessError` which caused an operation to fail, if any.
+pub(crate) fn access_error<T>(result: &Result<T, failure::Error>) -> Option<AccessError> {
+    match result {
+        Err(err) => err.downcast_ref::<AccessError>().cloned(),
+        Ok(_) => None,
+    }
 }
 
-fn get_tag_details_ru5102(tag: &ScanResult, reader: &mut ru5102::Reader) -> ScanResult {
+fn read_tag_details<R: Reader + ?Sized>(
+    reader: &mut R,
+    tag: &ScanResult,
+    password: u32,
+) -> ScanResult {
     let mut tag = tag.to_owned();
-    tag.tid = match read_tid(reader, &tag.epc, 0, 2) {
+    let tid = reader.read_memory(&tag.epc, MemoryBank::TID, 0, 2, password);
+    tag.access = access_result(&tid).or(tag.access);
+    tag.tid = match tid {
         Ok(res) => {
             debug!("Read TID: {:?}", res);
-            Some(decode_tid(&res).unwrap())
+            parse_tid(&res)
         },
         Err(_err) => None
     };
 
     tag.xtid_header = match tag.tid {
-        Some(tid) => {
-            if tid.xtid {
-                match read_tid(reader, &tag.epc, 2, 1) {
-                    Ok(res) => match decode_xtid_header(&res) {
-                        Ok(header) => Some(header),
-                        Err(_) => None,
-                    },
-                    Err(_) => None,
-                }
-            } else {
-                None
+        Some(tid) if tid.xtid => {
+            match reader.read_memory(&tag.epc, MemoryBank::TID, 2, 1, password) {
+                Ok(res) => decode_xtid_header(&res).ok(),
+                Err(_) => None,
             }
         }
-        None => None,
+        _ => None,
     };
 
-    tag.serial = match read_tid(reader, &tag.epc, 2, 3) {
-        Ok(data) => Some(data),
-        Err(_) => None,
-    };
+    tag.serial = reader.read_memory(&tag.epc, MemoryBank::TID, 2, 3, password).ok();
+
+    if tag.pc.is_none() {
+        tag.pc = reader
+            .read_memory(&tag.epc, MemoryBank::EPC, 1, 1, password)
+            .ok()
+            .and_then(|res| to_word(&res));
+    }
+    tag.xpc_w1 = read_xpc_w1(reader, &tag, password);
 
     tag
 }
 
-fn scan_ru5102(reader: &mut ru5102::Reader) -> Vec<ScanResult> {
-    let mut result = Vec::new();
-    let inv = reader.inventory().unwrap();
-    if inv.len() > 0 {
-        for uid in inv.iter() {
-            result.push(ScanResult::from_epc(uid.to_owned()));
+/// Read XPC_W1 if the tag's PC word says it has one.
+pub(crate) fn read_xpc_w1<R: Reader + ?Sized>(
+    reader: &mut R,
+    tag: &ScanResult,
+    password: u32,
+) -> Option<u16> {
+    match tag.pc.map(ProtocolControl::decode) {
+        Some(pc) if pc.xi => reader
+            .read_memory(&tag.epc, MemoryBank::EPC, 0x21, 1, password)
+            .ok()
+            .and_then(|res| to_word(&res)),
+        _ => None,
+    }
+}
+
+/// Write a new EPC to a tag, updating the length in its PC word, and read it back to verify.
+pub(crate) fn write_epc<R: Reader + ?Sized>(
+    reader: &mut R,
+    epc: &[u8],
+    new_epc: &[u8],
+    password: u32,
+) -> Result<(), failure::Error> {
+    if new_epc.is_empty() || !new_epc.len().is_multiple_of(2) || new_epc.len() > 62 {
+        bail!("EPC must be between 1 and 31 words long");
+    }
+    let pc = reader.read_memory(epc, MemoryBank::EPC, 1, 1, password)?;
+    if pc.len() < 2 {
+        bail!("Invalid PC read from tag: {}", hex::encode_upper(&pc));
+    }
+    let words = (new_epc.len() / 2) as u8;
+    // EPC length is the top five bits of the PC word, the rest is left unchanged.
+    let mut data = vec![(words << 3) | (pc[0] & 0x07), pc[1]];
+    data.extend(new_epc);
+    reader.write_memory(epc, MemoryBank::EPC, 1, &data, password)?;
+
+    let written = reader.read_memory(new_epc, MemoryBank::EPC, 2, words, password)?;
+    if written != new_epc {
+        return Err(format_err!(
+            "Verification failed: read back {}",
+            hex::encode_upper(&written)
+        ));
+    }
+    Ok(())
+}
+
+/// Read a memory bank word by word until the tag refuses.
+fn read_bank<R: Reader + ?Sized>(
+    reader: &mut R,
+    epc: &[u8],
+    bank: MemoryBank,
+    password: u32,
+) -> BankDump {
+    let mut dump = BankDump {
+        bank,
+        data: Vec::new(),
+        error: None,
+    };
+    for word in 0..MAX_BANK_WORDS {
+        match reader.read_memory(epc, bank, word, 1, password) {
+            Ok(data) => dump.data.extend(data),
+            Err(err) => {
+                debug!("Read of {:?} bank stopped at word {}: {}", bank, word, err);
+                dump.error = Some(err.to_string());
+                break;
+            }
         }
     }
-    result
+    dump
+}
+
+/// Read every memory bank of a tag.
+pub(crate) fn read_banks<R: Reader + ?Sized>(
+    reader: &mut R,
+    epc: &[u8],
+    password: u32,
+) -> MemoryDump {
+    MemoryDump {
+        banks: MEMORY_BANKS
+            .iter()
+            .map(|bank| read_bank(reader, epc, *bank, password))
+            .collect(),
+    }
 }
 
-fn scan_invelion(reader: &mut invelion::Reader) -> invelion::error::Result<Vec<ScanResult>> {
-    let mut result = Vec::new();
-    for i in 0..4 {
-        reader.set_work_antenna(i)?;
-        let inv = reader.real_time_inventory(255)?;
-        for item in inv.items.iter() {
-            let mut res = ScanResult::from_epc(item.epc.to_owned());
-            res.rssi = Some(item.rssi);
-            res.antenna = Some(item.antenna);
-            result.push(res);
+/// Whether an operation which needed access to a tag's memory was allowed, or None if it failed
+/// for some other reason.
+fn access_result<T>(result: &Result<T, failure::Error>) -> Option<Result<(), AccessError>> {
+    match (result, access_error(result)) {
+        (Ok(_), _) => Some(Ok(())),
+        (Err(_), Some(err)) => Some(Err(err)),
+        (Err(_), None) => None,
+    }
+}
+
+/// Show whether an operation which needed access to a tag's memory was allowed in its details.
+fn report_access<T>(tx: &EventSender, epc: &[u8], result: &Result<T, failure::Error>) {
+    let access = match access_result(result) {
+        Some(access) => access,
+        None => return,
+    };
+    let mut tag = ScanResult::from_epc(epc.to_owned());
+    tag.access = Some(access);
+    let _ = tx.send(ScanEvent::Tag(tag));
+}
+
+/// State of the scan thread, which can be changed by `ReaderCommand`s
+struct ScanState {
+    settings: ScanSettings,
+    passwords: Passwords,
+    /// Select mask to apply in software, if the reader can't do it
+    filter: Option<SelectMask>,
+    /// Select mask which was asked for, to set up again after reconnecting
+    select: Option<SelectMask>,
+    /// Inventory rounds which have failed in a row
+    failures: u32,
+    last_health: Option<time::Instant>,
+    /// Time each inventory round takes, if the reader doesn't do it itself
+    round_duration: Option<time::Duration>,
+}
+
+impl ScanState {
+    fn new(passwords: Passwords) -> ScanState {
+        ScanState {
+            settings: ScanSettings::default(),
+            passwords,
+            filter: None,
+            select: None,
+            failures: 0,
+            last_health: None,
+            round_duration: None,
+        }
+    }
+
+    fn wanted(&self, tag: &ScanResult) -> bool {
+        match &self.filter {
+            Some(filter) => filter.matches_epc(&tag.epc) != Some(false),
+            None => true,
         }
     }
-    Ok(result)
 }
 
-fn get_details_invelion(
-    reader: &mut invelion::Reader,
-    antenna: u8
-) -> invelion::error::Result<Vec<ScanResult>> {
-    reader.set_work_antenna(antenna)?;
-    let data = reader.read(invelion::protocol::MemoryBank::TID, &[0, 0, 0, 0], 0, 2)?;
-    Ok(data.iter().map(|response|
-        ScanResult {
-            epc: response.epc.to_owned(),
-            tid: match decode_tid(&response.data) {
-                Ok(tid) => Some(tid),
-                Err(_) => {warn!("decode_tid error: {:?}", response.data);
-                    None
+/// Check that a Select mask can be used with a reader, either by the reader itself or in
+/// software.
+pub(crate) fn check_select(
+    mask: &SelectMask,
+    capabilities: &Capabilities,
+) -> Result<(), failure::Error> {
+    if !capabilities.select && mask.matches_epc(&[]).is_none() {
+        bail!(
+            "This reader can only filter on the EPC bank from bit 0x{:X}",
+            EPC_START_BIT
+        );
+    }
+    Ok(())
+}
+
+/// Apply scan settings to the reader. Readers which don't accept a scan round duration have it
+/// applied by repeating inventory instead.
+fn configure(
+    reader: &mut dyn Reader,
+    settings: &ScanSettings,
+    state: &mut ScanState,
+) -> Result<ScanSettings, failure::Error> {
+    let mut accepted = reader.configure(settings)?;
+    state.round_duration = None;
+    if accepted.round_duration.is_none() && settings.round_duration.is_some() {
+        state.round_duration = settings.round_duration;
+        accepted.round_duration = settings.round_duration;
+    }
+    state.settings = accepted.to_owned();
+    Ok(accepted)
+}
+
+/// Set up a Select mask in the reader if it's supported, otherwise return it to be applied in
+/// software.
+fn select(
+    reader: &mut dyn Reader,
+    mask: Option<SelectMask>,
+) -> Result<Option<SelectMask>, failure::Error> {
+    let capabilities = reader.capabilities();
+    if let Some(mask) = &mask {
+        check_select(mask, &capabilities)?;
+    }
+    if capabilities.select {
+        reader.select(mask.as_ref())?;
+        return Ok(None);
+    }
+    Ok(mask)
+}
+
+fn handle_command(
+    reader: &mut dyn Reader,
+    command: ReaderCommand,
+    state: &mut ScanState,
+    tx: &EventSender,
+) {
+    let passwords = &mut state.passwords;
+    match command {
+        ReaderCommand::Settings { settings, reply } => {
+            let _ = reply.send(configure(reader, &settings, state));
+        }
+        ReaderCommand::WriteEPC {
+            epc,
+            new_epc,
+            reply,
+        } => {
+            let result = write_epc(reader, &epc, &new_epc, passwords.get(&epc));
+            report_access(tx, if result.is_ok() { &new_epc } else { &epc }, &result);
+            if result.is_ok() {
+                if let Some(password) = passwords.tags.remove(&epc) {
+                    passwords.tags.insert(new_epc, password);
                 }
-            },
-            rssi: None,
-            antenna: Some(response.antenna),
-            xtid_header: None,
-            serial: None,
-            last_seen: time::Instant::now()       
+            }
+            let _ = reply.send(result);
+        }
+        ReaderCommand::ReadBanks { epc, reply } => {
+            let _ = reply.send(read_banks(reader, &epc, passwords.get(&epc)));
         }
-    ).collect())
+        ReaderCommand::Lock {
+            epc,
+            target,
+            action,
+            password,
+            reply,
+        } => {
+            let password = password.unwrap_or_else(|| passwords.get(&epc));
+            let result = reader.lock(&epc, target, action, password);
+            report_access(tx, &epc, &result);
+            if result.is_ok() {
+                let mut tag = ScanResult::from_epc(epc);
+                tag.locks.insert(target, action);
+                let _ = tx.send(ScanEvent::Tag(tag));
+            }
+            let _ = reply.send(result);
+        }
+        ReaderCommand::SetPassword { epc, password } => match password {
+            Some(password) => {
+                passwords.tags.insert(epc, password);
+            }
+            None => {
+                passwords.tags.remove(&epc);
+            }
+        },
+        ReaderCommand::Select { mask, reply } => {
+            let result = select(reader, mask.clone()).map(|filter| {
+                state.filter = filter;
+                state.select = mask;
+            });
+            let _ = reply.send(result);
+        }
+    }
 }
 
-fn scan(reader_type: &mut ReaderType) -> Vec<ScanResult> {
-    match reader_type {
-        ReaderType::Invelion(reader) => match scan_invelion(reader) {
-            Ok(result) => result,
-            Err(err) => {
-                warn!("Scan error: {:?}", err);
-                vec![]
+/// Reply to a command which needs the reader while it's disconnected.
+fn reject_command(command: ReaderCommand, state: &mut ScanState) {
+    let err = || failure::err_msg("Reader disconnected");
+    match command {
+        ReaderCommand::Settings { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        ReaderCommand::WriteEPC { reply, .. }
+        | ReaderCommand::Lock { reply, .. }
+        | ReaderCommand::Select { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        // Dropping the reply shows that it can't be read
+        ReaderCommand::ReadBanks { .. } => {}
+        ReaderCommand::SetPassword { epc, password } => match password {
+            Some(password) => {
+                state.passwords.tags.insert(epc, password);
+            }
+            None => {
+                state.passwords.tags.remove(&epc);
             }
         },
-        ReaderType::RU5102(reader) => scan_ru5102(reader),
     }
 }
 
-pub(crate) fn scan_thread(
-    mut reader_type: ReaderType,
-    tx: mpsc::Sender<ScanResult>,
-    settings_rx: mpsc::Receiver<ScanSettings>,
-) {
-    let mut settings = ScanSettings::default();
-    let mut detailed_scan_antenna = 0;
+/// Why the scan thread stopped
+enum Stop {
+    /// The UI dropped its end of the commands channel
+    Closed,
+    /// Nothing is receiving results
+    Unreceived,
+}
+
+/// Handle any queued commands, or wait up to `timeout` for one if it's given.
+fn handle_commands(
+    mut reader: Option<&mut dyn Reader>,
+    commands_rx: &mpsc::Receiver<ReaderCommand>,
+    state: &mut ScanState,
+    tx: &EventSender,
+    timeout: Option<time::Duration>,
+) -> Result<(), Stop> {
+    let mut timeout = timeout;
     loop {
-        match settings_rx.try_recv() {
-            Ok(new_settings) => {
-                settings = new_settings;
-            }
-            Err(_) => {}
+        let command = match timeout.take() {
+            Some(timeout) => match commands_rx.recv_timeout(timeout) {
+                Ok(command) => command,
+                Err(mpsc::RecvTimeoutError::Timeout) => return Ok(()),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(Stop::Closed),
+            },
+            None => match commands_rx.try_recv() {
+                Ok(command) => command,
+                Err(mpsc::TryRecvError::Empty) => return Ok(()),
+                Err(mpsc::TryRecvError::Disconnected) => return Err(Stop::Closed),
+            },
+        };
+        match reader.as_deref_mut() {
+            Some(reader) => handle_command(reader, command, state, tx),
+            None => reject_command(command, state),
+        }
+    }
+}
+
+/// Whether an error means the reader has gone, rather than something which might not happen
+/// again.
+fn connection_lost(err: &failure::Error, connector: &Connector) -> bool {
+    let io_error = err
+        .iter_chain()
+        .any(|cause| cause.downcast_ref::<io::Error>().is_some());
+    io_error && !connector.present()
+}
+
+/// Count a failure, returning true if the connection should be given up on.
+fn failed(err: &failure::Error, state: &mut ScanState, connector: &Connector) -> bool {
+    state.failures += 1;
+    state.failures >= MAX_FAILURES || connection_lost(err, connector)
+}
+
+/// Send the reader's health if it's time to, returning false if it failed.
+fn check_health(
+    reader: &mut dyn Reader,
+    state: &mut ScanState,
+    tx: &EventSender,
+    connector: &Connector,
+) -> Result<bool, Stop> {
+    if state
+        .last_health
+        .is_some_and(|time| time.elapsed() < HEALTH_INTERVAL)
+    {
+        return Ok(true);
+    }
+    state.last_health = Some(time::Instant::now());
+    match reader.health() {
+        Ok(health) => {
+            tx.send(ScanEvent::Connected(health))?;
+            Ok(true)
         }
-        let tags = scan(&mut reader_type);
-        for tag in tags.iter() {
-            tx.send(tag.to_owned()).unwrap();
+        Err(err) => {
+            warn!("Error checking reader health: {:?}", err);
+            tx.send(ScanEvent::Error(ScanError::Health(err.to_string())))?;
+            Ok(!failed(&err, state, connector))
         }
+    }
+}
 
-        if settings.detailed_scan {
-            let tags = match &mut reader_type {
-                ReaderType::Invelion(reader) => match get_details_invelion(reader, detailed_scan_antenna) {
-                    Ok(result) => result,
+/// Open the reader again, and set it up as it was before the connection was lost.
+fn reconnect(
+    connector: &Connector,
+    state: &mut ScanState,
+) -> Result<Box<dyn Reader>, failure::Error> {
+    let mut reader = connector.open()?;
+    let settings = state.settings.to_owned();
+    configure(reader.as_mut(), &settings, state)?;
+    state.filter = select(reader.as_mut(), state.select.clone())?;
+    state.failures = 0;
+    state.last_health = None;
+    Ok(reader)
+}
+
+/// Inventory the tags in range, repeatedly for the scan round duration if it's applied here
+/// rather than by the reader.
+fn inventory(
+    reader: &mut dyn Reader,
+    state: &ScanState,
+) -> Result<Vec<ScanResult>, failure::Error> {
+    let end = match state.round_duration {
+        Some(duration) => time::Instant::now() + duration,
+        None => return reader.inventory(),
+    };
+    let mut tags = reader.inventory()?;
+    while time::Instant::now() < end {
+        tags.extend(reader.inventory()?);
+    }
+    Ok(tags)
+}
+
+/// Run an inventory round, then read the details of the tags found if detailed scans are on.
+///
+/// Reader errors are sent to the UI. Returns false if the connection to the reader has been
+/// lost.
+fn scan_round(
+    reader: &mut dyn Reader,
+    state: &mut ScanState,
+    tx: &EventSender,
+    connector: &Connector,
+) -> Result<bool, Stop> {
+    let tags: Vec<ScanResult> = match inventory(reader, state) {
+        Ok(result) => result.into_iter().filter(|tag| state.wanted(tag)).collect(),
+        Err(err) => {
+            warn!("Scan error: {:?}", err);
+            tx.send(ScanEvent::Error(ScanError::Inventory(err.to_string())))?;
+            // Don't spin if the reader has gone away
+            thread::sleep(ERROR_BACKOFF);
+            return Ok(!failed(&err, state, connector));
+        }
+    };
+    state.failures = 0;
+    for tag in tags.iter() {
+        tx.send(ScanEvent::Tag(tag.to_owned()))?;
+    }
+    tx.send(ScanEvent::Round(tags.len()))?;
+
+    if state.settings.detailed_scan {
+        match reader.details(&tags, &state.passwords) {
+            Ok(tags) => {
+                for tag in tags {
+                    tx.send(ScanEvent::Tag(tag))?;
+                }
+            }
+            Err(err) => {
+                warn!("Detailed scan error: {:?}", err);
+                tx.send(ScanEvent::Error(ScanError::Details(err.to_string())))?;
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Scan, and reconnect to the reader whenever the connection is lost.
+fn run_scan(
+    reader: Box<dyn Reader>,
+    connector: &Connector,
+    tx: &EventSender,
+    commands_rx: &mpsc::Receiver<ReaderCommand>,
+    state: &mut ScanState,
+) -> Result<(), Stop> {
+    let mut reader = Some(reader);
+    let mut attempts = 0;
+    loop {
+        let current = match &mut reader {
+            Some(current) => current,
+            None => {
+                attempts += 1;
+                tx.send(ScanEvent::Reconnecting(attempts))?;
+                match reconnect(connector, state) {
+                    Ok(reconnected) => {
+                        info!("Reconnected to the reader");
+                        attempts = 0;
+                        reader.insert(reconnected)
+                    }
                     Err(err) => {
-                        warn!("Detailed scan error: {:?}", err);
-                        vec![]
+                        warn!("Error reconnecting: {:?}", err);
+                        tx.send(ScanEvent::Error(ScanError::Connection(err.to_string())))?;
+                        handle_commands(None, commands_rx, state, tx, Some(RECONNECT_INTERVAL))?;
+                        continue;
                     }
-                },
-                ReaderType::RU5102(reader) => get_details_ru5102(&tags, reader)
-            };
-            for tag in tags {
-                tx.send(tag).unwrap();
+                }
+            }
+        };
+        handle_commands(Some(current.as_mut()), commands_rx, state, tx, None)?;
+        let connected = check_health(current.as_mut(), state, tx, connector)?
+            && scan_round(current.as_mut(), state, tx, connector)?;
+        if !connected {
+            warn!("Lost connection to the reader");
+            // Close the port before opening it again
+            reader = None;
+        }
+    }
+}
+
+/// Scan for tags until the UI goes away, which is when it drops its end of either channel.
+pub(crate) fn scan_thread(
+    reader: Box<dyn Reader>,
+    connector: Connector,
+    tx: EventSender,
+    commands_rx: mpsc::Receiver<ReaderCommand>,
+    passwords: Passwords,
+) {
+    let mut state = ScanState::new(passwords);
+    match run_scan(reader, &connector, &tx, &commands_rx, &mut state) {
+        Err(Stop::Closed) => info!("Scan thread stopping"),
+        Err(Stop::Unreceived) => info!("Scan thread stopping, nothing is receiving results"),
+        Ok(()) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::parse_select;
+
+    fn mask(input: &str) -> SelectMask {
+        parse_select(input).unwrap().unwrap()
+    }
+
+    fn epc(value: &str) -> Vec<u8> {
+        hex::decode(value).unwrap()
+    }
+
+    #[test]
+    fn selects_epc_prefixes() {
+        let prefix = mask("E2801");
+        assert_eq!(prefix.length, 20);
+        assert_eq!(
+            prefix.matches_epc(&epc("E28011005E5F4C7B165E7425")),
+            Some(true)
+        );
+        assert_eq!(
+            prefix.matches_epc(&epc("E2806894B7DCEA034DE38834")),
+            Some(false)
+        );
+        // Too short for the mask to match
+        assert_eq!(prefix.matches_epc(&epc("E280")), Some(false));
+        assert_eq!(
+            SelectMask::for_epc(&epc("E2801100")).matches_epc(&epc("E28011005E5F4C7B")),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn selects_gs1_company_prefixes() {
+        let sgtin = epc("3074257BF7194E4000001A85");
+        assert_eq!(mask("gs1:0614141").matches_epc(&sgtin), Some(true));
+        assert_eq!(mask("gs1:0614142").matches_epc(&sgtin), Some(false));
+        assert_eq!(
+            mask("urn:epc:tag:sgtin-96:3.0614141").matches_epc(&sgtin),
+            Some(true)
+        );
+        // Different filter value
+        assert_eq!(
+            mask("urn:epc:tag:sgtin-96:1.0614141").matches_epc(&sgtin),
+            Some(false)
+        );
+        assert_eq!(
+            mask("urn:epc:tag:sscc-96:3.0614141").matches_epc(&sgtin),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn selects_other_banks() {
+        let tid = mask("tid:E28011");
+        assert_eq!(tid.matches_epc(&epc("E28011005E5F4C7B165E7425")), None);
+        assert!(tid.matches(&epc("E2801105")));
+        assert!(!tid.matches(&epc("E2806894")));
+        let offset = mask("user:F@0x8");
+        assert!(offset.matches(&epc("00F0")));
+        assert!(!offset.matches(&epc("F000")));
+        // Masks which start before the EPC can't be checked against it
+        assert_eq!(mask("epc:3000@0x10").matches_epc(&epc("3074257B")), None);
+    }
+
+    #[test]
+    fn access_reports_keep_where_tags_were_seen() {
+        let mut tag = ScanResult::from_read(epc("E28011005E5F4C7B165E7425"));
+        tag.rssi = Some(-50);
+        tag.antenna = Some(2);
+        tag.reader = 1;
+        let seen = tag.last_seen;
+
+        let mut report = ScanResult::from_epc(tag.epc.to_owned());
+        report.access = Some(Err(AccessError::PasswordRequired));
+        report.last_seen = seen + time::Duration::from_secs(1);
+        tag.update(report);
+        assert_eq!(tag.access, Some(Err(AccessError::PasswordRequired)));
+        assert_eq!(tag.rssi, Some(-50));
+        assert_eq!(tag.antenna, Some(2));
+        assert_eq!(tag.reader, 1);
+        assert_eq!(tag.last_seen, seen);
+    }
+
+    /// A reader with one tag, which can be locked
+    struct LockReader;
+
+    impl Reader for LockReader {
+        fn capabilities(&self) -> Capabilities {
+            Capabilities::default()
+        }
+
+        fn antennas(&mut self) -> Result<Vec<u8>, failure::Error> {
+            Ok(vec![0])
+        }
+
+        fn inventory(&mut self) -> Result<Vec<ScanResult>, failure::Error> {
+            Ok(vec![ScanResult::from_read(epc("E28011005E5F4C7B165E7425"))])
+        }
+
+        fn read_memory(
+            &mut self,
+            _epc: &[u8],
+            _bank: MemoryBank,
+            _start: u8,
+            _words: u8,
+            _password: u32,
+        ) -> Result<Vec<u8>, failure::Error> {
+            bail!("Reading is not supported by this reader")
+        }
+
+        fn lock(
+            &mut self,
+            _epc: &[u8],
+            _target: LockTarget,
+            _action: LockAction,
+            _password: u32,
+        ) -> Result<(), failure::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn locking_records_the_lock_on_the_tag() {
+        let mut reader = LockReader;
+        let mut tag = reader.inventory().unwrap().remove(0);
+        tag.antenna = Some(1);
+        let seen = tag.last_seen;
+
+        let (tx, rx) = mpsc::channel();
+        let (reply, reply_rx) = mpsc::channel();
+        let command = ReaderCommand::Lock {
+            epc: tag.epc.to_owned(),
+            target: LockTarget::User,
+            action: LockAction::Lock,
+            password: None,
+            reply,
+        };
+        let mut state = ScanState::new(Passwords::default());
+        handle_command(&mut reader, command, &mut state, &EventSender::new(0, tx));
+        reply_rx.recv().unwrap().unwrap();
+
+        for event in rx.try_iter() {
+            if let ScanEvent::Tag(result) = event.event {
+                tag.update(result);
             }
+        }
+        assert_eq!(tag.locks.get(&LockTarget::User), Some(&LockAction::Lock));
+        assert_eq!(tag.access, Some(Ok(())));
+        assert_eq!(tag.antenna, Some(1));
+        assert_eq!(tag.last_seen, seen);
+    }
+
+    /// A reader whose tags only allow access with one password
+    struct PasswordReader(u32);
+
+    impl Reader for PasswordReader {
+        fn capabilities(&self) -> Capabilities {
+            Capabilities::default()
+        }
+
+        fn antennas(&mut self) -> Result<Vec<u8>, failure::Error> {
+            Ok(vec![0])
+        }
+
+        fn inventory(&mut self) -> Result<Vec<ScanResult>, failure::Error> {
+            Ok(vec![ScanResult::from_read(epc("E28011005E5F4C7B165E7425"))])
+        }
 
-            detailed_scan_antenna = (detailed_scan_antenna + 1) % 4;
+        fn read_memory(
+            &mut self,
+            _epc: &[u8],
+            _bank: MemoryBank,
+            _start: u8,
+            words: u8,
+            password: u32,
+        ) -> Result<Vec<u8>, failure::Error> {
+            if password != self.0 {
+                return Err(AccessError::WrongPassword.into());
+            }
+            let mut data = epc("E2801105");
+            data.resize(words as usize * 2, 0);
+            Ok(data)
         }
     }
+
+    #[test]
+    fn access_errors_clear_once_access_is_allowed() {
+        let mut reader = PasswordReader(0x1234_5678);
+        let mut tag = reader.inventory().unwrap().remove(0);
+
+        tag.update(read_tag_details(&mut reader, &tag, 0));
+        assert_eq!(tag.access, Some(Err(AccessError::WrongPassword)));
+        assert_eq!(tag.tid, None);
+
+        tag.update(read_tag_details(&mut reader, &tag, 0x1234_5678));
+        assert_eq!(tag.access, Some(Ok(())));
+        assert!(tag.tid.is_some());
+    }
+
+    #[test]
+    fn decodes_protocol_control() {
+        let pc = ProtocolControl::decode(0x3000);
+        assert_eq!(pc.length, 6);
+        assert!(!pc.umi && !pc.xi && !pc.toggle);
+        assert_eq!(pc.to_string(), "6 word EPC, GS1 attributes 0x00");
+        let pc = ProtocolControl::decode(0x37A5);
+        assert_eq!(pc.length, 6);
+        assert!(pc.umi && pc.xi && pc.toggle);
+        assert_eq!(pc.nsi, 0xA5);
+        assert_eq!(pc.to_string(), "6 word EPC, user memory, XPC, ISO AFI 0xA5");
+    }
+
+    #[test]
+    fn decodes_extended_protocol_control() {
+        let xpc = ExtendedProtocolControl::decode(0x0000);
+        assert!(!xpc.xeb && xpc.flags.is_empty());
+        assert_eq!(xpc.to_string(), "XEB=0");
+        let xpc = ExtendedProtocolControl::decode(0x880D);
+        assert!(xpc.xeb);
+        assert_eq!(
+            xpc.to_string(),
+            "XEB=1, SA (sensor alarm), U (untraceable), K (killable), H (hazmat)"
+        );
+        // The RFU bits aren't flags
+        assert!(ExtendedProtocolControl::decode(0x7000).flags.is_empty());
+    }
 }
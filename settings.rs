@@ -0,0 +1,298 @@
+//! Panel for changing scan settings, showing what the reader accepted.
+use crate::block;
+use crate::rfid::{ScanSettings, Session, Target};
+use std::sync::mpsc;
+use std::time;
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Paragraph, Text, Widget};
+
+const SESSIONS: &[Session] = &[Session::S0, Session::S1, Session::S2, Session::S3];
+const TARGETS: &[Target] = &[Target::A, Target::B, Target::AB];
+const MAX_Q: u8 = 15;
+const ROUND_DURATIONS: &[u64] = &[50, 100, 200, 500, 1000, 2000];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    DetailedScan,
+    Antenna(u8),
+    Power,
+    Session,
+    Target,
+    Q,
+    RoundDuration,
+}
+
+/// Step through `values`, where None (leave the reader's setting) comes before the first value.
+fn step<T: Copy + PartialEq>(values: &[T], current: Option<T>, forward: bool) -> Option<T> {
+    let index = current.and_then(|current| values.iter().position(|v| *v == current));
+    let index = match (index, forward) {
+        (None, true) => Some(0),
+        (None, false) => None,
+        (Some(i), true) => Some(std::cmp::min(i + 1, values.len() - 1)),
+        (Some(0), false) => None,
+        (Some(i), false) => Some(i - 1),
+    };
+    index.map(|i| values[i])
+}
+
+fn describe(field: Field, settings: &ScanSettings) -> String {
+    fn or_dash<T>(value: Option<T>, f: impl Fn(T) -> String) -> String {
+        value.map(f).unwrap_or_else(|| "-".to_string())
+    }
+    match field {
+        Field::DetailedScan => if settings.detailed_scan { "on" } else { "off" }.to_string(),
+        Field::Antenna(antenna) => match &settings.antennas {
+            Some(antennas) if !antennas.contains(&antenna) => "off".to_string(),
+            Some(_) => "on".to_string(),
+            None => "-".to_string(),
+        },
+        Field::Power => or_dash(settings.power, |power| format!("{} dBm", power)),
+        Field::Session => or_dash(settings.session, |session| format!("{:?}", session)),
+        Field::Target => or_dash(settings.target, |target| format!("{:?}", target)),
+        Field::Q => or_dash(settings.q, |q| q.to_string()),
+        Field::RoundDuration => or_dash(settings.round_duration, |duration| {
+            format!("{} ms", duration.as_millis())
+        }),
+    }
+}
+
+fn name(field: Field) -> String {
+    match field {
+        Field::DetailedScan => "Detailed scan".to_string(),
+        Field::Antenna(antenna) => format!("Antenna {}", antenna),
+        Field::Power => "TX power".to_string(),
+        Field::Session => "Session".to_string(),
+        Field::Target => "Target".to_string(),
+        Field::Q => "Q".to_string(),
+        Field::RoundDuration => "Scan round duration".to_string(),
+    }
+}
+
+/// Scan settings being edited, and the settings the reader last accepted
+pub(crate) struct SettingsState {
+    pub requested: ScanSettings,
+    pub accepted: ScanSettings,
+    /// Antenna ports the reader has
+    antennas: Vec<u8>,
+    /// Whether any reader accepts session, target and Q
+    session: bool,
+    /// Highest TX power any reader can be set to, or None if none of them can be
+    max_power: Option<u8>,
+    row: usize,
+    pending: Option<mpsc::Receiver<Result<ScanSettings, failure::Error>>>,
+    /// Replies still to come, one from each reader the settings were sent to
+    replies: usize,
+    /// The settings accepted by the readers which have replied so far
+    replied: Option<ScanSettings>,
+    message: Option<String>,
+}
+
+impl SettingsState {
+    pub fn new(
+        accepted: ScanSettings,
+        antennas: Vec<u8>,
+        session: bool,
+        max_power: Option<u8>,
+    ) -> SettingsState {
+        SettingsState {
+            requested: accepted.to_owned(),
+            accepted,
+            antennas,
+            session,
+            max_power,
+            row: 0,
+            pending: None,
+            replies: 0,
+            replied: None,
+            message: None,
+        }
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        let mut fields = vec![Field::DetailedScan];
+        fields.extend(self.antennas.iter().map(|antenna| Field::Antenna(*antenna)));
+        fields.extend(&[
+            Field::Power,
+            Field::Session,
+            Field::Target,
+            Field::Q,
+            Field::RoundDuration,
+        ]);
+        fields
+    }
+
+    /// Whether a setting can be changed, which it can't if no reader supports it.
+    fn supported(&self, field: Field) -> bool {
+        match field {
+            Field::Power => self.max_power.is_some(),
+            Field::Session | Field::Target | Field::Q => self.session,
+            _ => true,
+        }
+    }
+
+    pub fn select(&mut self, reverse: bool) {
+        let count = self.fields().len();
+        self.row = if reverse {
+            (self.row + count - 1) % count
+        } else {
+            (self.row + 1) % count
+        };
+    }
+
+    /// Change the selected setting to its next or previous value.
+    pub fn change(&mut self, forward: bool) {
+        let field = self.fields()[self.row];
+        if !self.supported(field) {
+            return;
+        }
+        let available = &self.antennas;
+        let settings = &mut self.requested;
+        match field {
+            Field::DetailedScan => settings.detailed_scan = !settings.detailed_scan,
+            Field::Antenna(antenna) => {
+                let mut antennas = settings
+                    .antennas
+                    .to_owned()
+                    .unwrap_or_else(|| available.to_owned());
+                match antennas.iter().position(|a| *a == antenna) {
+                    Some(i) => {
+                        antennas.remove(i);
+                    }
+                    None => {
+                        antennas.push(antenna);
+                        antennas.sort();
+                    }
+                }
+                settings.antennas = Some(antennas);
+            }
+            Field::Power => {
+                let powers: Vec<u8> = (0..=self.max_power.unwrap_or(0)).collect();
+                settings.power = step(&powers, settings.power, forward);
+            }
+            Field::Session => settings.session = step(SESSIONS, settings.session, forward),
+            Field::Target => settings.target = step(TARGETS, settings.target, forward),
+            Field::Q => {
+                let values: Vec<u8> = (0..=MAX_Q).collect();
+                settings.q = step(&values, settings.q, forward);
+            }
+            Field::RoundDuration => {
+                let times: Vec<time::Duration> = ROUND_DURATIONS
+                    .iter()
+                    .map(|ms| time::Duration::from_millis(*ms))
+                    .collect();
+                settings.round_duration = step(&times, settings.round_duration, forward);
+            }
+        }
+    }
+
+    /// Wait for `replies` readers to reply to a change of settings.
+    pub fn start(
+        &mut self,
+        reply: mpsc::Receiver<Result<ScanSettings, failure::Error>>,
+        replies: usize,
+    ) {
+        self.pending = Some(reply);
+        self.replies = replies;
+        self.replied = None;
+        self.message = Some("Applying...".to_string());
+    }
+
+    /// Check whether the readers have replied. Antennas are numbered on each reader, so an
+    /// antenna counts as accepted if any reader accepted it.
+    pub fn poll(&mut self) {
+        let result = match &self.pending {
+            Some(rx) => match rx.try_recv() {
+                Ok(Ok(accepted)) => {
+                    let merged = match self.replied.take() {
+                        Some(mut merged) => {
+                            if let (Some(antennas), Some(more)) =
+                                (&mut merged.antennas, accepted.antennas)
+                            {
+                                antennas.extend(more);
+                                antennas.sort_unstable();
+                                antennas.dedup();
+                            }
+                            merged
+                        }
+                        None => accepted,
+                    };
+                    self.replies = self.replies.saturating_sub(1);
+                    if self.replies > 0 {
+                        self.replied = Some(merged);
+                        return;
+                    }
+                    Ok(merged)
+                }
+                Ok(Err(err)) => Err(err),
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => Err(failure::err_msg("Reader stopped")),
+            },
+            None => return,
+        };
+        self.pending = None;
+        self.message = Some(match result {
+            Ok(accepted) if accepted == self.requested => {
+                self.accepted = accepted;
+                "Settings applied".to_string()
+            }
+            Ok(accepted) => {
+                self.accepted = accepted;
+                "Some settings weren't accepted by the reader".to_string()
+            }
+            Err(err) => format!("Failed: {}", err),
+        });
+    }
+}
+
+pub(crate) struct SettingsView<'a> {
+    pub state: &'a SettingsState,
+}
+
+impl<'a> SettingsView<'a> {
+    pub fn new(state: &'a SettingsState) -> SettingsView<'a> {
+        SettingsView { state }
+    }
+}
+
+impl<'a> Widget for SettingsView<'a> {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        let state = self.state;
+        let mut text = vec![Text::styled(
+            format!("  {:<20}{:<20}{}\n", "Setting", "Requested", "Accepted"),
+            Style::default().modifier(Modifier::BOLD),
+        )];
+        for (i, field) in state.fields().into_iter().enumerate() {
+            let line = format!(
+                "{} {:<20}{:<20}{}\n",
+                if i == state.row { ">" } else { " " },
+                name(field),
+                describe(field, &state.requested),
+                describe(field, &state.accepted)
+            );
+            text.push(if !state.supported(field) {
+                Text::styled(line, Style::default().fg(Color::DarkGray))
+            } else if i == state.row {
+                Text::styled(line, Style::default().fg(Color::Yellow))
+            } else {
+                Text::raw(line)
+            });
+        }
+        text.push(Text::raw(
+            "\nUp/Down to select, Left/Right to change, Enter to apply, Esc to close.\n\
+             '-' leaves the reader's setting unchanged, or means it isn't supported.\n\
+             Greyed out settings aren't supported by the reader.\n",
+        ));
+        if let Some(message) = &state.message {
+            text.push(Text::styled(
+                message.to_owned(),
+                Style::default().fg(Color::Yellow),
+            ));
+        }
+
+        Paragraph::new(text.iter())
+            .block(block("Scan Settings"))
+            .draw(area, buf);
+    }
+}
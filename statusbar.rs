@@ -0,0 +1,102 @@
+//! A line at the bottom of the screen for each reader, showing what it's doing.
+use crate::app::ReaderStatus;
+use crate::rfid::ReaderHealth;
+use std::time;
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::{Color, Style};
+use tui::widgets::{Paragraph, Text, Widget};
+
+/// Errors are shown for this long after they happen
+const ERROR_SHOWN: time::Duration = time::Duration::from_secs(30);
+
+pub(crate) struct StatusBar<'a> {
+    pub status: &'a [ReaderStatus],
+    /// The readers, as PORT:DRIVER
+    pub readers: &'a [String],
+}
+
+impl<'a> StatusBar<'a> {
+    pub fn new(status: &'a [ReaderStatus], readers: &'a [String]) -> StatusBar<'a> {
+        StatusBar { status, readers }
+    }
+}
+
+fn describe_health(health: &ReaderHealth) -> String {
+    let mut text = "connected".to_string();
+    if let Some(firmware) = &health.firmware {
+        text.push_str(&format!(", firmware {}", firmware));
+    }
+    if let Some(temperature) = health.temperature {
+        text.push_str(&format!(", {}°C", temperature));
+    }
+    text
+}
+
+/// The status of one reader, on its own line.
+fn reader_status<'a>(status: &ReaderStatus, reader: &str) -> Vec<Text<'a>> {
+    let mut text = vec![];
+    if status.stopped {
+        text.push(Text::styled(
+            format!(
+                "{}: stopped, no more tags will be seen. See the log for details.",
+                reader
+            ),
+            Style::default().fg(Color::Red),
+        ));
+    } else if let Some(attempt) = status.reconnecting {
+        text.push(Text::styled(
+            format!(
+                "{}: connection lost, reconnecting (attempt {})",
+                reader, attempt
+            ),
+            Style::default().fg(Color::Yellow),
+        ));
+    } else {
+        let connection = match &status.health {
+            Some(health) => describe_health(health),
+            None => "starting".to_string(),
+        };
+        text.push(Text::raw(match status.last_round {
+            Some(_) => format!(
+                "{}: {} | {} reads in the last round",
+                reader, connection, status.round_reads
+            ),
+            None => format!("{}: {}", reader, connection),
+        }));
+    }
+    if !status.stopped {
+        if let Some((err, time)) = &status.error {
+            if time.elapsed() < ERROR_SHOWN {
+                text.push(Text::styled(
+                    format!(
+                        " | {} ({}s ago, {} errors this session)",
+                        err,
+                        time.elapsed().as_secs(),
+                        status.errors
+                    ),
+                    Style::default().fg(Color::Red),
+                ));
+            }
+        }
+    }
+    text
+}
+
+impl<'a> Widget for StatusBar<'a> {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        let mut text = vec![];
+        for (i, (status, reader)) in self.status.iter().zip(self.readers).enumerate() {
+            if i > 0 {
+                text.push(Text::raw("\n"));
+            }
+            // Numbered as in the tag table's Reader column
+            let reader = match self.readers.len() {
+                1 => reader.to_owned(),
+                _ => format!("#{} {}", i + 1, reader),
+            };
+            text.extend(reader_status(status, &reader));
+        }
+        Paragraph::new(text.iter()).draw(area, buf);
+    }
+}
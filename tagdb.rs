@@ -0,0 +1,206 @@
+//! Database of every tag seen, kept across sessions.
+//!
+//! The database is a file of JSON `TagRecord`s, one per line. Updated records are appended, and
+//! the latest record for each tag wins when it's loaded.
+use crate::export::wall_clock;
+use crate::record::{RecordedTID, RecordedXTIDHeader};
+use crate::rfid::ScanResult;
+use chrono::{DateTime, Local};
+use failure::format_err;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time;
+
+/// How often changed records are written out
+const FLUSH_INTERVAL: time::Duration = time::Duration::from_secs(30);
+/// Rewrite the file on startup if it has this many times more lines than tags
+const COMPACT_RATIO: usize = 4;
+
+/// Everything known about a tag
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct TagRecord {
+    pub epc: String,
+    pub tid: Option<RecordedTID>,
+    pub xtid_header: Option<RecordedXTIDHeader>,
+    pub serial: Option<String>,
+    /// RFC 3339 time the tag was first seen
+    pub first_seen: String,
+    /// RFC 3339 time the tag was last seen
+    pub last_seen: String,
+    /// Readers which have seen the tag, as PORT:DRIVER
+    #[serde(default)]
+    pub readers: BTreeSet<String>,
+    #[serde(default)]
+    pub antennas: BTreeSet<u8>,
+    /// Number of sessions the tag has been seen in
+    #[serde(default)]
+    pub sessions: u32,
+}
+
+impl TagRecord {
+    fn new(result: &ScanResult) -> TagRecord {
+        let seen = wall_clock(result.last_seen).to_rfc3339();
+        TagRecord {
+            epc: hex::encode_upper(&result.epc),
+            tid: None,
+            xtid_header: None,
+            serial: None,
+            first_seen: seen.to_owned(),
+            last_seen: seen,
+            readers: BTreeSet::new(),
+            antennas: BTreeSet::new(),
+            sessions: 0,
+        }
+    }
+}
+
+/// Format an RFC 3339 time from a `TagRecord` in local time.
+pub(crate) fn local_time(time: &str) -> String {
+    match DateTime::parse_from_rfc3339(time) {
+        Ok(time) => time
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string(),
+        Err(_) => time.to_string(),
+    }
+}
+
+pub(crate) struct TagDb {
+    path: String,
+    /// The readers tags are being seen by, as PORT:DRIVER
+    readers: Vec<String>,
+    tags: HashMap<Vec<u8>, TagRecord>,
+    /// Records of tags from before this session
+    previous: HashMap<Vec<u8>, TagRecord>,
+    /// Tags seen this session
+    seen: HashSet<Vec<u8>>,
+    /// Tags whose records have changed since they were last written
+    dirty: HashSet<Vec<u8>>,
+    last_flush: time::Instant,
+}
+
+fn load(path: &str) -> Result<(HashMap<Vec<u8>, TagRecord>, usize), failure::Error> {
+    let mut tags = HashMap::new();
+    if !Path::new(path).exists() {
+        return Ok((tags, 0));
+    }
+    let file = File::open(path).map_err(|e| format_err!("Unable to open {}: {}", path, e))?;
+    let mut lines = 0;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: TagRecord = serde_json::from_str(&line)
+            .map_err(|e| format_err!("{}:{}: invalid tag record: {}", path, i + 1, e))?;
+        let epc = hex::decode(&record.epc)
+            .map_err(|e| format_err!("{}:{}: invalid EPC: {}", path, i + 1, e))?;
+        tags.insert(epc, record);
+        lines += 1;
+    }
+    Ok((tags, lines))
+}
+
+fn write_records<'a>(
+    file: File,
+    records: impl Iterator<Item = &'a TagRecord>,
+) -> Result<(), failure::Error> {
+    let mut writer = BufWriter::new(file);
+    for record in records {
+        serde_json::to_writer(&mut writer, record)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+impl TagDb {
+    /// Open the database at `path`, creating it if it doesn't exist.
+    pub fn open(path: &str, readers: &[String]) -> Result<TagDb, failure::Error> {
+        let (tags, lines) = load(path)?;
+        if lines > tags.len() * COMPACT_RATIO {
+            info!("Compacting tag database from {} lines", lines);
+            let tmp_path = format!("{}.tmp", path);
+            write_records(File::create(&tmp_path)?, tags.values())?;
+            fs::rename(&tmp_path, path)?;
+        }
+        Ok(TagDb {
+            path: path.to_string(),
+            readers: readers.to_vec(),
+            previous: tags.clone(),
+            tags,
+            seen: HashSet::new(),
+            dirty: HashSet::new(),
+            last_flush: time::Instant::now(),
+        })
+    }
+
+    /// The tag's record from before this session, if it had been seen before.
+    pub fn previous(&self, epc: &[u8]) -> Option<&TagRecord> {
+        self.previous.get(epc)
+    }
+
+    /// Record a result for a tag. Only reads in an inventory round count as the tag being seen,
+    /// so other results just fill in the details of tags which have been read.
+    pub fn update(&mut self, result: &ScanResult) {
+        if !result.read && !self.tags.contains_key(&result.epc) {
+            return;
+        }
+        let record = self
+            .tags
+            .entry(result.epc.to_owned())
+            .or_insert_with(|| TagRecord::new(result));
+        if let Some(tid) = &result.tid {
+            record.tid = Some(RecordedTID::new(tid));
+        }
+        if let Some(xtid) = &result.xtid_header {
+            record.xtid_header = Some(RecordedXTIDHeader::new(xtid));
+        }
+        if let Some(serial) = &result.serial {
+            record.serial = Some(hex::encode_upper(serial));
+        }
+        if result.read {
+            if self.seen.insert(result.epc.to_owned()) {
+                record.sessions += 1;
+            }
+            if let Some(antenna) = result.antenna {
+                record.antennas.insert(antenna);
+            }
+            record
+                .readers
+                .insert(self.readers[result.reader].to_owned());
+            record.last_seen = wall_clock(result.last_seen).to_rfc3339();
+        }
+        self.dirty.insert(result.epc.to_owned());
+    }
+
+    /// Append changed records to the file.
+    pub fn flush(&mut self) -> Result<(), failure::Error> {
+        self.last_flush = time::Instant::now();
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format_err!("Unable to open {}: {}", self.path, e))?;
+        let tags = &self.tags;
+        write_records(file, self.dirty.iter().filter_map(|epc| tags.get(epc)))?;
+        self.dirty.clear();
+        Ok(())
+    }
+
+    /// Write out changed records if it's been long enough since the last time.
+    pub fn poll(&mut self) {
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            if let Err(err) = self.flush() {
+                warn!("Error writing tag database: {:?}", err);
+            }
+        }
+    }
+}
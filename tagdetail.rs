@@ -1,35 +1,183 @@
-use crate::rfid::ScanResult;
+use crate::app::{RssiSample, TagStats};
+use crate::labels::Annotation;
+use crate::rfid::{ExtendedProtocolControl, ProtocolControl, ScanResult};
+use crate::tagdb::{local_time, TagDb};
 use crate::block;
-use tui::layout::Rect;
+use std::collections::BTreeMap;
+use std::time;
+use tui::layout::{Constraint, Direction, Layout, Rect};
 use gs1::epc;
 use tui::buffer::Buffer;
-use tui::style::{Style, Modifier};
-use tui::widgets::{Widget, Text, Paragraph};
+use tui::style::{Color, Style, Modifier};
+use tui::widgets::{Block, Widget, Text, Paragraph, Sparkline};
+
+/// Time covered by each column of an RSSI sparkline
+const SPARKLINE_STEP: time::Duration = time::Duration::from_millis(250);
+/// RSSI shown as an empty sparkline column
+const RSSI_FLOOR: i64 = -100;
+/// RSSI shown as a full sparkline column
+const RSSI_CEILING: i64 = -20;
 
 pub(crate) struct TagDetail<'a> {
-    pub item: Option<&'a ScanResult>
+    pub item: Option<&'a ScanResult>,
+    pub stats: Option<&'a TagStats>,
+    pub db: Option<&'a TagDb>,
+    pub label: Option<&'a Annotation>
 }
 
 impl<'a> TagDetail<'a> {
-    pub fn new(item: Option<&'a ScanResult>) -> TagDetail<'a> {
+    pub fn new(
+        item: Option<&'a ScanResult>,
+        stats: Option<&'a TagStats>,
+        db: Option<&'a TagDb>,
+        label: Option<&'a Annotation>
+    ) -> TagDetail<'a> {
         TagDetail {
-            item: item
+            item,
+            stats,
+            db,
+            label
         }
     }
 }
 
-fn render_detail(item: &ScanResult) -> Vec<Text> {
-    let mut header = format!("Tag ID: {}", hex::encode_upper(&item.epc));
-    match epc::decode_binary(&item.epc) {
-        Ok(val) => {
-            header.push_str(&format!(" ({})", val.to_uri()));
+/// The strongest RSSI in each `SPARKLINE_STEP` up to now, most recent last, with 0 where the
+/// tag wasn't seen.
+fn sparkline_data(samples: &[&RssiSample], columns: usize) -> Vec<u64> {
+    let mut data = vec![0; columns];
+    for sample in samples {
+        let age = sample.time.elapsed().as_millis() / SPARKLINE_STEP.as_millis();
+        if age < columns as u128 {
+            let value = (i64::from(sample.rssi) - RSSI_FLOOR).max(1) as u64;
+            let column = &mut data[columns - 1 - age as usize];
+            *column = (*column).max(value);
+        }
+    }
+    data
+}
+
+/// Draw an RSSI sparkline for each reader antenna which has seen the tag.
+fn draw_rssi(stats: &TagStats, area: Rect, buf: &mut Buffer) {
+    let mut antennas: BTreeMap<(usize, Option<u8>), Vec<&RssiSample>> = BTreeMap::new();
+    for sample in stats.history.iter() {
+        antennas.entry((sample.reader, sample.antenna)).or_default().push(sample);
+    }
+    if antennas.is_empty() || area.height < 2 {
+        return;
+    }
+    let height = (area.height / antennas.len() as u16).max(2);
+    for (i, ((reader, antenna), samples)) in antennas.iter().enumerate() {
+        let top = area.y + i as u16 * height;
+        if top + 2 > area.bottom() {
+            break;
         }
-        Err(_) => {}
+        let rssi: Vec<i8> = samples.iter().map(|sample| sample.rssi).collect();
+        let title = format!(
+            "{}{}: {} dBm (min {}, max {})",
+            if stats.readers.len() > 1 {
+                format!("Reader #{} ", reader + 1)
+            } else {
+                "".to_string()
+            },
+            match antenna {
+                Some(antenna) => format!("Antenna {}", antenna),
+                None => "RSSI".to_string()
+            },
+            rssi[rssi.len() - 1],
+            rssi.iter().min().unwrap(),
+            rssi.iter().max().unwrap()
+        );
+        let data = sparkline_data(samples, area.width as usize);
+        Sparkline::default()
+            .block(Block::default().title(&title))
+            .data(&data)
+            .max((RSSI_CEILING - RSSI_FLOOR) as u64)
+            .style(Style::default().fg(Color::Green))
+            .draw(Rect::new(area.x, top, area.width, height.min(area.bottom() - top)), buf);
+    }
+}
+
+fn render_stats(stats: &TagStats) -> String {
+    let mut line = format!(
+        "Reads: {} ({:.1}/s), first seen {}s ago",
+        stats.reads,
+        stats.read_rate(),
+        stats.first_seen.elapsed().as_secs()
+    );
+    if !stats.antenna_reads.is_empty() {
+        let antennas: Vec<String> = stats.antenna_reads
+            .iter()
+            .map(|(antenna, reads)| format!("{}: {}", antenna, reads))
+            .collect();
+        line.push_str(&format!(", by antenna {}", antennas.join(", ")));
+    }
+    if stats.reader_reads.len() > 1 {
+        let readers: Vec<String> = stats.reader_reads
+            .iter()
+            .map(|(reader, reads)| format!("#{}: {}", reader + 1, reads))
+            .collect();
+        line.push_str(&format!(", by reader {}", readers.join(", ")));
+    }
+    line.push('\n');
+    line
+}
+
+/// Whether the tag was seen in earlier sessions, and when
+fn render_history(item: &ScanResult, db: &TagDb) -> String {
+    match db.previous(&item.epc) {
+        Some(record) => format!(
+            "Seen in {} earlier session{}, first {}, last {}\n",
+            record.sessions,
+            if record.sessions == 1 { "" } else { "s" },
+            local_time(&record.first_seen),
+            local_time(&record.last_seen)
+        ),
+        None => "Not seen in earlier sessions\n".to_string()
+    }
+}
+
+fn render_detail<'a>(
+    item: &'a ScanResult,
+    stats: Option<&TagStats>,
+    db: Option<&TagDb>,
+    label: Option<&Annotation>
+) -> Vec<Text<'a>> {
+    let mut header = format!("Tag ID: {}", hex::encode_upper(&item.epc));
+    if let Ok(val) = epc::decode_binary(&item.epc) {
+        header.push_str(&format!(" ({})", val.to_uri()));
     };
     header.push('\n');
 
     vec![
         Text::styled(header, Style::default().modifier(Modifier::BOLD)),
+        match label {
+            Some(label) => Text::styled(format!("{}\n", label), Style::default().fg(Color::Cyan)),
+            None => Text::raw("")
+        },
+        Text::raw(match stats {
+            Some(stats) => render_stats(stats),
+            None => "".to_string()
+        }),
+        Text::raw(match db {
+            Some(db) => render_history(item, db),
+            None => "".to_string()
+        }),
+        match item.access {
+            Some(Err(err)) => Text::styled(format!("{}\n", err), Style::default().fg(Color::Red)),
+            _ => Text::raw("")
+        },
+        Text::raw(match item.pc {
+            Some(pc) => format!("PC: {:04X} ({})\n", pc, ProtocolControl::decode(pc)),
+            None => "".to_string()
+        }),
+        Text::raw(match item.xpc_w1 {
+            Some(xpc_w1) => format!(
+                "XPC_W1: {:04X} ({})\n",
+                xpc_w1,
+                ExtendedProtocolControl::decode(xpc_w1)
+            ),
+            None => "".to_string()
+        }),
         Text::raw(match item.tid {
             Some(tid) => format!("{:?}\n", tid),
             None => "".to_string()
@@ -39,8 +187,17 @@ fn render_detail(item: &ScanResult) -> Vec<Text> {
             None => "".to_string()
         }),
         Text::raw(match &item.serial {
-            Some(serial) => format!("Serial: {}", hex::encode(serial)),
+            Some(serial) => format!("Serial: {}\n", hex::encode(serial)),
             None => "".to_string()
+        }),
+        Text::raw(if item.locks.is_empty() {
+            "".to_string()
+        } else {
+            let locks: Vec<String> = item.locks
+                .iter()
+                .map(|(target, action)| format!("{} {}", target, action))
+                .collect();
+            format!("Locks set: {}\n", locks.join(", "))
         })
     ]
 }
@@ -49,13 +206,26 @@ impl<'a> Widget for TagDetail<'a> {
     fn draw(&mut self, area: Rect, buf: &mut Buffer) {
 
         let text = match self.item {
-            Some(item) => render_detail(&item),
+            Some(item) => render_detail(item, self.stats, self.db, self.label),
             None => vec![]
         };
 
+        let mut outer = block("Detail");
+        outer.draw(area, buf);
+        let inner = outer.inner(area);
+        let rects = match self.stats {
+            Some(stats) if !stats.history.is_empty() => Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                .split(inner),
+            _ => vec![inner]
+        };
+
         Paragraph::new(text.iter())
-            .block(block("Detail"))
             .wrap(true)
-            .draw(area, buf);
+            .draw(rects[0], buf);
+        if let (Some(stats), Some(rect)) = (self.stats, rects.get(1)) {
+            draw_rssi(stats, *rect, buf);
+        }
     }
 }
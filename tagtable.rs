@@ -1,34 +1,50 @@
-use crate::rfid::ScanResult;
+use crate::app::{App, SortColumn, TagStats};
+use crate::labels::Annotation;
+use crate::rfid::{ProtocolControl, ScanResult};
+use std::time::Duration;
 use crate::block;
 use tui::layout::Rect;
 use tui::buffer::Buffer;
 use tui::widgets::{Widget, Table, Row};
 use tui::style::{Color, Style, Modifier};
-use gs1::{epc, epc::tid::mdid_name, epc::tid::tmid_name};
+use gs1::epc;
 
-fn render_row(item: &ScanResult) -> Vec<String> {
-    let mut epc_str = match epc::decode_binary(&item.epc) {
-        Ok(val) => val.to_uri(),
-        Err(_) => hex::encode_upper(&item.epc)
-    };
+/// A short description of a length of time, such as "90s" or "5m"
+pub(crate) fn short_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 120 {
+        format!("{}s", secs)
+    } else if secs < 7200 {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}h", secs / 3600)
+    }
+}
 
-    if epc_str == "urn:epc:id:unprogrammed" {
-        epc_str = hex::encode_upper(&item.epc);
+/// The tag's EPC as a URI, or in hex if it can't be decoded
+pub(crate) fn epc_name(item: &ScanResult) -> String {
+    match epc::decode_binary(&item.epc) {
+        Ok(val) if val.to_uri() != "urn:epc:id:unprogrammed" => val.to_uri(),
+        _ => hex::encode_upper(&item.epc)
     }
+}
 
+fn render_row(
+    item: &ScanResult,
+    stats: Option<&TagStats>,
+    annotation: Option<&Annotation>
+) -> Vec<String> {
     vec![
-        epc_str,
-        match item.tid {
-            Some(tid) => mdid_name(&tid.mdid).to_string(),
-            None => "".to_string()
+        match stats {
+            Some(stats) => stats.name.to_owned(),
+            None => epc_name(item)
         },
-        match item.tid {
-            Some(tid) => match tmid_name(&tid.mdid, &tid.tmid) {
-                "Unknown" => format!("0x{:X}", &tid.tmid),
-                found => found.to_string()
-            },
+        match annotation {
+            Some(annotation) => annotation.to_string(),
             None => "".to_string()
         },
+        item.manufacturer().unwrap_or_default(),
+        item.model().unwrap_or_default(),
         match item.xtid_header {
             Some(_) => "Y",
             None => ""
@@ -37,6 +53,11 @@ fn render_row(item: &ScanResult) -> Vec<String> {
             Some(_) => "Y",
             None => ""
         }.to_string(),
+        match item.pc.map(ProtocolControl::decode) {
+            Some(pc) if pc.umi => "Y",
+            Some(_) => "N",
+            None => ""
+        }.to_string(),
         match item.rssi {
             Some(val) => format!("{}", val),
             None => "".to_string()
@@ -45,51 +66,188 @@ fn render_row(item: &ScanResult) -> Vec<String> {
             Some(val) => format!("{}", val),
             None => "".to_string()
         },
+        format!("#{}", item.reader + 1),
+        match stats {
+            Some(stats) => stats.reads.to_string(),
+            None => "".to_string()
+        },
+        match stats {
+            Some(stats) => format!("{:.1}", stats.read_rate()),
+            None => "".to_string()
+        },
+        match stats {
+            Some(stats) => short_duration(stats.first_seen.elapsed()),
+            None => "".to_string()
+        },
         format!("{}s", item.last_seen.elapsed().as_secs())
     ]
 }
 
+/// A column of the table
+struct Column {
+    header: &'static str,
+    width: u16,
+    /// When the table is too narrow for every column, they're left out in this order starting
+    /// from 1. Columns with 0 are always shown.
+    drop: u8
+}
+
+const COLUMNS: &[Column] = &[
+    Column { header: "ID", width: MIN_ID_WIDTH, drop: 0 },
+    Column { header: "Label", width: 14, drop: 9 },
+    Column { header: "Manufacturer", width: 18, drop: 8 },
+    Column { header: "Model", width: 10, drop: 7 },
+    Column { header: "XTID", width: 5, drop: 1 },
+    Column { header: "Serial", width: 6, drop: 2 },
+    Column { header: "User", width: 5, drop: 3 },
+    Column { header: "RSSI", width: 5, drop: 0 },
+    Column { header: "Ant", width: 4, drop: 11 },
+    Column { header: "Rdr", width: 4, drop: 10 },
+    Column { header: "Reads", width: 7, drop: 5 },
+    Column { header: "Rate", width: 6, drop: 6 },
+    Column { header: "First", width: 6, drop: 4 },
+    Column { header: "Age", width: 5, drop: 0 }
+];
+
+/// Narrowest the ID column gets, which fits a 96-bit EPC in hex
+const MIN_ID_WIDTH: u16 = 24;
+/// Widest the ID column gets, which fits most tag URIs
+const MAX_ID_WIDTH: u16 = 50;
+
+/// Index of the Reader column, which is only shown when there's more than one reader
+const READER_COLUMN: usize = 9;
+
+/// The columns to show in a table `width` wide, with their widths.
+///
+/// Columns are left out until the rest fit, apart from `keep`. The ID column then takes any
+/// space left over, up to `MAX_ID_WIDTH`.
+fn layout_columns(width: u16, hidden: Option<usize>, keep: usize) -> Vec<(usize, u16)> {
+    let mut shown: Vec<usize> = (0..COLUMNS.len()).filter(|i| Some(*i) != hidden).collect();
+    // Each column is followed by a space
+    let needed = |shown: &[usize]| shown.iter().map(|i| COLUMNS[*i].width + 1).sum::<u16>();
+    let mut optional: Vec<usize> = shown
+        .iter()
+        .cloned()
+        .filter(|i| COLUMNS[*i].drop > 0 && *i != keep)
+        .collect();
+    optional.sort_by_key(|i| COLUMNS[*i].drop);
+    for column in optional {
+        if needed(&shown) <= width {
+            break;
+        }
+        shown.retain(|i| *i != column);
+    }
+    let spare = std::cmp::min(width.saturating_sub(needed(&shown)), MAX_ID_WIDTH - MIN_ID_WIDTH);
+    shown
+        .into_iter()
+        .map(|i| match i {
+            0 => (i, MIN_ID_WIDTH + spare),
+            _ => (i, COLUMNS[i].width)
+        })
+        .collect()
+}
+
+/// Index of the column in the table which a sort applies to
+fn sort_column_index(sort: SortColumn) -> usize {
+    match sort {
+        SortColumn::EPC => 0,
+        SortColumn::Label => 1,
+        SortColumn::Manufacturer => 2,
+        SortColumn::Model => 3,
+        SortColumn::RSSI => 7,
+        SortColumn::Antenna => 8,
+        SortColumn::Reader => 9,
+        SortColumn::Reads => 10,
+        SortColumn::Rate => 11,
+        SortColumn::FirstSeen => 12,
+        SortColumn::Age => 13
+    }
+}
+
+/// The area which table rows are drawn in, inside the border and below the header.
+pub(crate) fn rows_area(area: Rect) -> Rect {
+    let inner = block("").inner(area);
+    // The header takes a line, followed by a blank line
+    let header = std::cmp::min(inner.height, 2);
+    Rect::new(inner.x, inner.y + header, inner.width, inner.height - header)
+}
 
 pub(crate) struct TagTable<'a> {
-    pub items: &'a Vec<&'a ScanResult>,
-    pub selected: Option<Vec<u8>>
+    pub items: &'a [&'a ScanResult],
+    pub app: &'a App,
+    /// Number of rows scrolled past at the top of the table
+    pub scroll: usize
 }
 
 impl<'a> TagTable<'a> {
-    pub fn new(items: &'a Vec<&'a ScanResult>, selected: Option<Vec<u8>>) -> TagTable<'a> {
+    pub fn new(items: &'a [&'a ScanResult], app: &'a App, scroll: usize) -> TagTable<'a> {
         TagTable {
-            items: items,
-            selected: selected
+            items,
+            app,
+            scroll
         }
     }
 }
 
 impl<'a> Widget for TagTable<'a> {
     fn draw(&mut self, area: Rect, buf: &mut Buffer) {
-        let header = ["ID", "Manufacturer", "Model", "XTID", "Serial", "RSSI", "Ant", "Age"];
+        let app = self.app;
+        let (sort, reverse) = (app.sort, app.sort_reverse);
+        let hidden = if app.readers.len() < 2 { Some(READER_COLUMN) } else { None };
+        // The sorted column stays, so it's clear what the order is
+        let sorted = sort_column_index(sort);
+        let columns = layout_columns(block("").inner(area).width, hidden, sorted);
+        let header: Vec<String> = columns.iter().map(|(i, _)| {
+            let mut header = COLUMNS[*i].header.to_string();
+            if *i == sorted {
+                header.push(if sort.descending() != reverse { '▼' } else { '▲' });
+            }
+            header
+        }).collect();
+        let widths: Vec<u16> = columns.iter().map(|(_, width)| *width).collect();
         let selected_style = Style::default().fg(Color::Yellow);
         let normal_style = Style::default();
-        let rows = self.items.iter().map(|item| {
+        let rows = self.items.iter().skip(self.scroll).map(|item| {
             let mut style = normal_style;
             if item.last_seen.elapsed().as_secs() > 2 {
                 style = style.fg(Color::Gray);
             }
-            match &self.selected {
-                Some(selected) => {
-                    if &item.epc == selected {
-                        style = selected_style;
-                    }
+            if let Some(selected) = &app.selected {
+                if &item.epc == selected {
+                    style = selected_style;
                 }
-                None => {}
             };
 
-            let cols = render_row(item).into_iter();
-            Row::StyledData(cols, style)
+            let mut cols = render_row(item, app.stats.get(&item.epc), app.labels.get(&item.epc));
+            let cols: Vec<String> = columns
+                .iter()
+                .map(|(i, _)| std::mem::take(&mut cols[*i]))
+                .collect();
+            Row::StyledData(cols.into_iter(), style)
         });
-        Table::new(header.into_iter(), rows)
+        let mut title = match &app.filter {
+            Some(filter) => format!("Tags (Select {})", filter),
+            None => "Tags".to_string()
+        };
+        if let Some(search) = &app.search {
+            title.push_str(&format!(" matching '{}'", search));
+        }
+        if let Some(reader) = app.reader_filter {
+            title.push_str(&format!(" seen by #{} {}", reader + 1, app.readers[reader]));
+        }
+        let visible = rows_area(area).height as usize;
+        if self.items.len() > visible {
+            title.push_str(&format!(
+                " [{}-{} of {}]",
+                self.scroll + 1,
+                std::cmp::min(self.scroll + visible, self.items.len()),
+                self.items.len()
+            ));
+        }
+        Table::new(header.iter(), rows)
             .header_style(Style::default().modifier(Modifier::BOLD))
-            .block(block("Tags"))
-            .widths(&[50, 25, 10, 6, 6, 8, 6, 9])
+            .block(block(&title))
+            .widths(&widths)
             .draw(area, buf);
     }
 }
@@ -3,11 +3,12 @@ use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
-use termion::event::Key;
+use termion::event::{self as term_event, Key, MouseEvent};
 use termion::input::TermRead;
 
 pub enum Event<I> {
     Input(I),
+    Mouse(MouseEvent),
     Tick,
 }
 
@@ -29,8 +30,10 @@ pub struct Config {
 impl Default for Config {
     fn default() -> Config {
         Config {
-            exit_key: Key::Char('q'),
-            tick_rate: Duration::from_millis(250),
+            // Not 'q', as that may be typed into a prompt
+            exit_key: Key::Ctrl('c'),
+            // Often enough for find mode to give real-time feedback
+            tick_rate: Duration::from_millis(100),
         }
     }
 }
@@ -46,17 +49,15 @@ impl Events {
             let tx = tx.clone();
             thread::spawn(move || {
                 let stdin = io::stdin();
-                for evt in stdin.keys() {
-                    match evt {
-                        Ok(key) => {
-                            if let Err(_) = tx.send(Event::Input(key)) {
-                                return;
-                            }
-                            if key == config.exit_key {
-                                return;
-                            }
-                        }
-                        Err(_) => {}
+                for event in stdin.events().flatten() {
+                    let event = match event {
+                        term_event::Event::Key(key) => Event::Input(key),
+                        term_event::Event::Mouse(mouse) => Event::Mouse(mouse),
+                        term_event::Event::Unsupported(_) => continue,
+                    };
+                    let exit = matches!(event, Event::Input(key) if key == config.exit_key);
+                    if tx.send(event).is_err() || exit {
+                        return;
                     }
                 }
             })